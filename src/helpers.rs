@@ -1,3 +1,6 @@
+pub mod content_type;
+pub mod image_helper;
+pub mod object_store;
 pub mod s3_helper;
 pub mod sqs_helper;
 pub mod es_helper;
\ No newline at end of file
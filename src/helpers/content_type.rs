@@ -0,0 +1,92 @@
+pub const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Detects a content type from the leading bytes of `body`, falling back to
+/// the extension of `key` for formats without a signature, such as JSON.
+pub fn detect_content_type(key: &str, body: &[u8]) -> String {
+    sniff_content_type(body)
+        .or_else(|| content_type_from_extension(key))
+        .unwrap_or(OCTET_STREAM)
+        .to_string()
+}
+
+/// Content type from magic bytes, if `body` starts with a known signature.
+pub fn sniff_content_type(body: &[u8]) -> Option<&'static str> {
+    if body.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if body.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]) {
+        Some("image/png")
+    } else if body.starts_with(b"GIF87a") || body.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if body.len() >= 12 && &body[..4] == b"RIFF" && &body[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else if body.len() >= 12 && &body[4..8] == b"ftyp" {
+        match &body[8..12] {
+            b"heic" | b"heix" | b"hevc" | b"hevx" | b"heim" | b"heis" => Some("image/heic"),
+            b"mif1" | b"msf1" => Some("image/heif"),
+            b"avif" | b"avis" => Some("image/avif"),
+            _ => None
+        }
+    } else if body.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else if body.starts_with(&[0x1F, 0x8B]) {
+        Some("application/gzip")
+    } else {
+        None
+    }
+}
+
+pub fn content_type_from_extension(key: &str) -> Option<&'static str> {
+    let (_, extension) = key.rsplit_once('.')?;
+    match extension.to_ascii_lowercase().as_str() {
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "png" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        "heic" => Some("image/heic"),
+        "avif" => Some("image/avif"),
+        "svg" => Some("image/svg+xml"),
+        "json" => Some("application/json"),
+        "geojson" => Some("application/geo+json"),
+        "pbf" | "mvt" => Some("application/x-protobuf"),
+        "pdf" => Some("application/pdf"),
+        "gz" => Some("application/gzip"),
+        "html" => Some("text/html; charset=utf-8"),
+        "css" => Some("text/css; charset=utf-8"),
+        "js" => Some("text/javascript; charset=utf-8"),
+        "txt" => Some("text/plain; charset=utf-8"),
+        "csv" => Some("text/csv; charset=utf-8"),
+        "xml" => Some("application/xml"),
+        _ => None
+    }
+}
+
+/// Conventional file extension for a content type, ignoring parameters.
+pub fn extension_for_content_type(content_type: &str) -> Option<&'static str> {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match essence.as_str() {
+        "image/jpeg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/heic" => Some("heic"),
+        "image/avif" => Some("avif"),
+        "image/svg+xml" => Some("svg"),
+        "application/json" => Some("json"),
+        "application/geo+json" => Some("geojson"),
+        "application/x-protobuf" => Some("pbf"),
+        "application/pdf" => Some("pdf"),
+        "application/gzip" => Some("gz"),
+        "text/html" => Some("html"),
+        "text/css" => Some("css"),
+        "text/javascript" => Some("js"),
+        "text/plain" => Some("txt"),
+        "text/csv" => Some("csv"),
+        "application/xml" => Some("xml"),
+        _ => None
+    }
+}
@@ -3,7 +3,9 @@ use std::collections::HashMap;
 
 use elasticsearch::{auth::Credentials, cert::CertificateValidation, http::{transport::{SingleNodeConnectionPool, TransportBuilder}, Url}, indices::{IndicesCreateParts, IndicesDeleteParts, IndicesExistsParts}, BulkOperation, BulkParts, Elasticsearch, SearchParts};
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+
+use crate::model::{geo::{Feature, GeoPoint}, savedsearch::SavedSearchNotification, search::TripReportSearchRepresentation, tripreport::TripReport, zone::{huts_within_shape_query, huts_within_zone_query, zones_containing_point_query, ZoneShapeDocument}};
 
 #[derive(Clone)]
 pub struct ESHelper {
@@ -81,17 +83,30 @@ impl ESHelper {
     }
 
     pub async fn bulk_index<T>(&self, index: &str, bulk_ops: Vec<T>) -> Result<(), ElasticsearchBulkIndexError> where T: Serialize {
+        self.send_bulk_index(index, bulk_ops.into_iter().map(|b| (None, b)).collect()).await
+    }
+
+    /// Like `bulk_index`, but with caller supplied document ids so the
+    /// documents can be referenced later, e.g. from an `indexed_shape`.
+    pub async fn bulk_index_with_ids<T>(&self, index: &str, bulk_ops: Vec<(String, T)>) -> Result<(), ElasticsearchBulkIndexError> where T: Serialize {
+        self.send_bulk_index(index, bulk_ops.into_iter().map(|(id, b)| (Some(id), b)).collect()).await
+    }
+
+    async fn send_bulk_index<T>(&self, index: &str, bulk_ops: Vec<(Option<String>, T)>) -> Result<(), ElasticsearchBulkIndexError> where T: Serialize {
         let body: Vec<BulkOperation<_>> = bulk_ops
             .iter()
-            .map(|b| BulkOperation::index(b).into())
-            .collect(); 
+            .map(|(id, b)| match id {
+                Some(id) => BulkOperation::index(b).id(id).into(),
+                None => BulkOperation::index(b).into()
+            })
+            .collect();
 
         let bulk_res = self.client
             .bulk(BulkParts::Index(index))
             .body(body)
             .send()
             .await
-            .map_err(|err| ElasticsearchBulkIndexError{message: err.to_string()})?;
+            .map_err(|err| ElasticsearchBulkIndexError{message: err.to_string(), failed_ids: vec![]})?;
         let code = bulk_res.status_code();
         if !code.is_success() {
             let reason = bulk_res
@@ -99,14 +114,178 @@ impl ESHelper {
                 .await
                 .unwrap_or("failed to get text from elasticsearch response body".to_string());
             return Err(ElasticsearchBulkIndexError {
-                message: format!("non success status code received when trying to bulk index: {}: {}", code, reason)
+                message: format!("non success status code received when trying to bulk index: {}: {}", code, reason),
+                failed_ids: vec![]
             })
         }
 
-        Ok(())
+        // a 200 can still carry per-document rejections, e.g. an invalid
+        // geo_shape
+        let json: Value = bulk_res
+            .json()
+            .await
+            .map_err(|err| ElasticsearchBulkIndexError{message: format!("failed to get json from elasticsearch response body: {}", err), failed_ids: vec![]})?;
+        if json["errors"].as_bool() != Some(true) {
+            return Ok(());
+        }
+        let failures: Vec<(String, String)> = json["items"]
+            .as_array()
+            .unwrap_or(&vec![])
+            .iter()
+            .filter_map(|item| {
+                let result = &item["index"];
+                let error = result.get("error")?;
+                let reason = error["reason"].as_str().unwrap_or("unknown reason").to_string();
+                Some((result["_id"].as_str().unwrap_or_default().to_string(), reason))
+            })
+            .collect();
+        let reasons: Vec<String> = failures
+            .iter()
+            .map(|(id, reason)| format!("{}: {}", id, reason))
+            .collect();
+
+        Err(ElasticsearchBulkIndexError {
+            message: format!("failed to index {} of {} documents: {}", failures.len(), bulk_ops.len(), reasons.join("; ")),
+            failed_ids: failures.into_iter().map(|(id, _)| id).collect()
+        })
+    }
+
+    pub async fn index_zone_shapes(&self, index: &str, features: &[Feature]) -> Result<(), ElasticsearchBulkIndexError> {
+        let docs: Vec<(String, ZoneShapeDocument)> = features
+            .iter()
+            .map(ZoneShapeDocument::from_feature)
+            .map(|doc| (doc.zone_id.clone(), doc))
+            .collect();
+
+        self.bulk_index_with_ids(index, docs).await
+    }
+
+    /// Huts in `hut_index` inside `shape`, a GeoJSON geometry.
+    pub async fn huts_within_shape(&self, hut_index: &str, shape: &Value, size: usize) -> Result<Vec<ElasticsearchHit>, ElasticsearchSearchError> {
+        self.search(hut_index, json!({
+            "query": { "bool": { "filter": huts_within_shape_query(shape) } },
+            "size": size
+        })).await
+    }
+
+    /// Huts in `hut_index` inside the zone indexed in `zone_index` as `zone_id`.
+    pub async fn huts_within_zone(&self, hut_index: &str, zone_index: &str, zone_id: &str, size: usize) -> Result<Vec<ElasticsearchHit>, ElasticsearchSearchError> {
+        self.search(hut_index, json!({
+            "query": { "bool": { "filter": huts_within_zone_query(zone_index, zone_id) } },
+            "size": size
+        })).await
+    }
+
+    /// The zone containing `point`, typically a hut's location. Where zones
+    /// overlap, the first match is returned.
+    pub async fn zone_containing_point(&self, zone_index: &str, point: &GeoPoint) -> Result<Option<ZoneShapeDocument>, ElasticsearchSearchError> {
+        let hits = self.search(zone_index, json!({
+            "query": { "bool": { "filter": zones_containing_point_query(point) } },
+            "size": 1
+        })).await?;
+
+        hits
+            .into_iter()
+            .next()
+            .map(|hit|
+                serde_json::from_value(hit._source)
+                    .map_err(|err| ElasticsearchSearchError{message: format!("failed to parse zone shape: {}", err)})
+            )
+            .transpose()
     }
 
     pub async fn search(&self, index: &str, body: Value) -> Result<Vec<ElasticsearchHit>, ElasticsearchSearchError> {
+        let json = self.send_search(index, body).await?;
+        Self::parse_hits(&json)
+    }
+
+    /// Runs `body` with a phrase suggester attached for `text`. Suggestions
+    /// are only returned when fewer than `config.min_hits` hits come back.
+    pub async fn search_with_suggestions(&self, index: &str, mut body: Value, text: &str, config: &PhraseSuggestConfig) -> Result<SearchWithSuggestions, ElasticsearchSearchError> {
+        let Some(body_obj) = body.as_object_mut() else {
+            return Err(ElasticsearchSearchError {
+                message: "search body must be a json object to attach suggestions".to_string()
+            })
+        };
+        body_obj.insert("suggest".to_string(), config.to_suggest(text));
+
+        let json = self.send_search(index, body).await?;
+        let hits = Self::parse_hits(&json)?;
+        if hits.len() >= config.min_hits {
+            return Ok(SearchWithSuggestions { hits, suggestions: vec![] })
+        }
+
+        let mut suggestions: Vec<ElasticsearchSuggestion> = json["suggest"][PHRASE_SUGGESTION_NAME]
+            .as_array()
+            .unwrap_or(&vec![])
+            .iter()
+            .flat_map(|entry| entry["options"].as_array().cloned().unwrap_or_default())
+            .map(|option|
+                serde_json::from_value(option)
+                    .map_err(|err| ElasticsearchSearchError{message: format!("failed to get elasticsearch suggestions: {}", err)})
+            )
+            .collect::<Result<_, _>>()?;
+        suggestions.retain(|suggestion| !suggestion.text.eq_ignore_ascii_case(text.trim()));
+
+        Ok(SearchWithSuggestions { hits, suggestions })
+    }
+
+    /// Finds the saved searches in `index` whose percolator queries match an
+    /// approved trip report, producing one notification per match to send
+    /// as `Job::NotifySavedSearch`. Returns nothing for unapproved reports.
+    pub async fn saved_search_notifications(&self, index: &str, trip_report: &TripReport) -> Result<Vec<SavedSearchNotification>, ElasticsearchSearchError> {
+        if !trip_report.approved {
+            return Ok(vec![])
+        }
+
+        let document = TripReportSearchRepresentation::from_trip_report(trip_report);
+        let mut notifications: Vec<SavedSearchNotification> = Vec::new();
+        let mut search_after: Option<Vec<Value>> = None;
+        loop {
+            // search_after rather than from, which ES caps at
+            // index.max_result_window. saved_search_id breaks _doc ties
+            // between shards.
+            let mut body = json!({
+                "query": {
+                    "percolate": {
+                        "field": "query",
+                        "document": document
+                    }
+                },
+                "_source": ["saved_search_id", "user_id"],
+                "sort": ["_doc", "saved_search_id"],
+                "track_scores": true,
+                "size": PERCOLATE_PAGE_SIZE
+            });
+            if let Some(search_after) = &search_after {
+                body["search_after"] = json!(search_after);
+            }
+            let hits = self.search(index, body).await?;
+            let page_len = hits.len();
+            search_after = hits.last().map(|hit| hit.sort.clone());
+
+            for hit in hits {
+                let saved_search_id = hit._source["saved_search_id"].as_str().unwrap_or(&hit._id).to_string();
+                let user_id = hit._source["user_id"]
+                    .as_str()
+                    .ok_or_else(|| ElasticsearchSearchError{message: format!("saved search {} has no user_id", saved_search_id)})?
+                    .to_string();
+                notifications.push(SavedSearchNotification {
+                    saved_search_id,
+                    user_id,
+                    trip_report_id: trip_report.id.clone()
+                });
+            }
+
+            if page_len < PERCOLATE_PAGE_SIZE {
+                break;
+            }
+        }
+
+        Ok(notifications)
+    }
+
+    async fn send_search(&self, index: &str, body: Value) -> Result<Value, ElasticsearchSearchError> {
         let search_res = self.client
             .search(SearchParts::Index(&[index]))
             .body(body)
@@ -124,23 +303,22 @@ impl ESHelper {
             })
         }
 
-        let json: Value = search_res
+        search_res
             .json()
             .await
-            .map_err(|err| ElasticsearchSearchError{message: format!("failed to get json from elasticsearch response body: {}", err.to_string())})?;
+            .map_err(|err| ElasticsearchSearchError{message: format!("failed to get json from elasticsearch response body: {}", err)})
+    }
 
-        let hits: Result<Vec<ElasticsearchHit>, ElasticsearchSearchError> = json["hits"]["hits"]
+    fn parse_hits(json: &Value) -> Result<Vec<ElasticsearchHit>, ElasticsearchSearchError> {
+        json["hits"]["hits"]
             .as_array()
             .unwrap_or(&vec![])
-            .to_owned()
             .iter()
             .map(|hit| 
                 serde_json::from_value(hit.to_owned())
                     .map_err(|err| ElasticsearchSearchError{message: format!("failed to get elasticsearch hits: {}", err)})
             )
-            .collect();
-
-        Ok(hits?)
+            .collect()
     }
 
     pub fn parse_scores_from_hits(hits: Vec<ElasticsearchHit>, key: &str) -> HashMap<String, f32> {
@@ -158,7 +336,72 @@ pub struct ElasticsearchHit {
     pub _id: String,
     pub _index: String,
     pub _score: f32,
-    pub _source: Value
+    pub _source: Value,
+    /// Sort values of the hit, present when the search had a `sort`.
+    #[serde(default)]
+    pub sort: Vec<Value>
+}
+
+const PERCOLATE_PAGE_SIZE: usize = 500;
+
+const PHRASE_SUGGESTION_NAME: &str = "did_you_mean";
+
+#[derive(Clone, Debug)]
+pub struct PhraseSuggestConfig {
+    pub field: String,
+    pub size: usize,
+    pub max_errors: f32,
+    pub min_hits: usize
+}
+
+impl Default for PhraseSuggestConfig {
+
+    fn default() -> Self {
+        Self {
+            field: "name.trigram".to_string(),
+            size: 3,
+            max_errors: 2.0,
+            min_hits: 3
+        }
+    }
+}
+
+impl PhraseSuggestConfig {
+
+    fn to_suggest(&self, text: &str) -> Value {
+        json!({
+            "text": text,
+            PHRASE_SUGGESTION_NAME: {
+                "phrase": {
+                    "field": self.field,
+                    "size": self.size,
+                    "gram_size": 3,
+                    "max_errors": self.max_errors,
+                    "direct_generator": [{
+                        "field": self.field,
+                        "suggest_mode": "always"
+                    }],
+                    "highlight": {
+                        "pre_tag": "<em>",
+                        "post_tag": "</em>"
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ElasticsearchSuggestion {
+    pub text: String,
+    pub highlighted: Option<String>,
+    pub score: f32
+}
+
+#[derive(Debug)]
+pub struct SearchWithSuggestions {
+    pub hits: Vec<ElasticsearchHit>,
+    pub suggestions: Vec<ElasticsearchSuggestion>
 }
 
 #[derive(Debug)]
@@ -220,7 +463,10 @@ impl std::error::Error for ElasticsearchCreateIndexError {
 
 #[derive(Debug)]
 pub struct ElasticsearchBulkIndexError {
-    pub message: String
+    pub message: String,
+    /// Ids of the documents Elasticsearch rejected, when the request itself
+    /// succeeded. The other documents were indexed.
+    pub failed_ids: Vec<String>
 }
 
 impl fmt::Display for ElasticsearchBulkIndexError {
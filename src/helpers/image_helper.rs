@@ -0,0 +1,875 @@
+use std::{collections::{BTreeMap, HashMap}, fmt, io::Cursor};
+
+use image::{codecs::{jpeg::JpegEncoder, webp::WebPEncoder}, imageops::FilterType, DynamicImage, ImageDecoder, ImageEncoder, ImageFormat, ImageReader, Limits};
+use image::metadata::Orientation;
+
+use crate::model::{object::{ImgLinkUpdates, S3PathAttributes}, objectkey::{hut_prefix, quarantined_trip_report_prefix, trip_report_prefix, ImageVariant, ObjectKey}, tripreport::TripReport};
+
+use super::{content_type::sniff_content_type, object_store::{e_tag, ObjectStore}, s3_helper::{DeleteObjectFailure, PutObjectOptions}};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageOutputFormat {
+    WebP,
+    Jpeg
+}
+
+impl ImageOutputFormat {
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageOutputFormat::WebP => "webp",
+            ImageOutputFormat::Jpeg => "jpg"
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ImageOutputFormat::WebP => "image/webp",
+            ImageOutputFormat::Jpeg => "image/jpeg"
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ImagePipelineConfig {
+    pub thumbnail_width: u32,
+    pub thumbnail_height: u32,
+    pub widths: Vec<u32>,
+    /// Every variant is written in each format. The first one is used for
+    /// the returned links. WebP output is lossless, so it's usually larger
+    /// than JPEG for photos and isn't written by default.
+    pub formats: Vec<ImageOutputFormat>,
+    pub jpeg_quality: u8,
+    pub cache_control: Option<String>,
+    /// Prepended to keys to build the returned links, e.g. a CDN origin.
+    pub link_prefix: String
+}
+
+impl Default for ImagePipelineConfig {
+
+    fn default() -> Self {
+        Self {
+            thumbnail_width: 400,
+            thumbnail_height: 300,
+            widths: vec![640, 1280, 1920],
+            formats: vec![ImageOutputFormat::Jpeg],
+            jpeg_quality: 80,
+            cache_control: Some("public, max-age=31536000, immutable".to_string()),
+            link_prefix: String::new()
+        }
+    }
+}
+
+/// What to keep of the uploaded image's EXIF metadata. Orientation is
+/// always applied to the pixels and reset, whatever the policy.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExifPolicy {
+    Strip,
+    /// Keeps camera and exposure details but drops the GPS block. Falls
+    /// back to stripping everything if the EXIF can't be parsed.
+    KeepWithoutGps
+}
+
+#[derive(Clone, Debug)]
+pub struct ImageValidationConfig {
+    pub max_size: usize,
+    pub max_width: u32,
+    pub max_height: u32,
+    pub output_format: ImageOutputFormat,
+    pub jpeg_quality: u8,
+    pub exif: ExifPolicy
+}
+
+impl Default for ImageValidationConfig {
+
+    fn default() -> Self {
+        Self {
+            max_size: 20 * 1024 * 1024,
+            max_width: 8192,
+            max_height: 8192,
+            output_format: ImageOutputFormat::Jpeg,
+            jpeg_quality: 85,
+            exif: ExifPolicy::Strip
+        }
+    }
+}
+
+/// An upload that passed validation, re-encoded in the configured format.
+#[derive(Clone, Debug)]
+pub struct ValidatedImage {
+    pub bytes: Vec<u8>,
+    pub format: ImageOutputFormat,
+    pub width: u32,
+    pub height: u32
+}
+
+/// Outcome of `promote_trip_report_images`.
+#[derive(Clone, Debug, Default)]
+pub struct PromotionReport {
+    /// Quarantined key to public key of every promoted image.
+    pub promoted: HashMap<String, String>,
+    /// Uploads that failed validation. They're purged from quarantine.
+    pub rejected: Vec<PromotionFailure>,
+    /// Uploads that couldn't be read or written, left in quarantine for a
+    /// retry.
+    pub failed: Vec<PromotionFailure>,
+    /// Quarantined uploads that were promoted or rejected but couldn't be
+    /// deleted.
+    pub delete_failures: Vec<DeleteObjectFailure>
+}
+
+#[derive(Clone, Debug)]
+pub struct PromotionFailure {
+    pub key: String,
+    pub message: String
+}
+
+/// Links produced by `generate_image_variants` for one original.
+#[derive(Clone, Debug)]
+pub struct ImageVariantLinks {
+    /// The thumbnail and a link to the original itself, following the
+    /// `ImgLinkUpdates` convention of one image link per photo.
+    pub links: ImgLinkUpdates,
+    /// Resized copies keyed by width, for a `srcset`. Empty when the
+    /// original is narrower than every configured width.
+    pub srcset: BTreeMap<u32, String>
+}
+
+struct EncodedVariant {
+    key: String,
+    width: u32,
+    format: ImageOutputFormat,
+    bytes: Vec<u8>
+}
+
+/// Reads the original image at `key`, writes a cropped thumbnail and a
+/// resized copy for every configured width narrower than the original, and
+/// returns links to the results. Orientation from EXIF is applied first.
+pub async fn generate_image_variants(store: &dyn ObjectStore, key: &str, config: &ImagePipelineConfig) -> Result<ImageVariantLinks, ImageProcessingError> {
+    let Some(format) = config.formats.first().copied() else {
+        return Err(ImageProcessingError{message: "at least one output format is required".to_string()});
+    };
+    let original = store
+        .get(key)
+        .await
+        .map_err(|err| ImageProcessingError{message: format!("failed to get original image {}: {}", key, err)})?
+        .ok_or_else(|| ImageProcessingError{message: format!("original image {} does not exist", key)})?;
+
+    let owned_key = key.to_string();
+    let owned_config = config.clone();
+    let (thumbnails, responsive) = tokio::task::spawn_blocking(move || encode_variants(&owned_key, &original, &owned_config))
+        .await
+        .map_err(|err| ImageProcessingError{message: format!("image processing task failed: {}", err)})??;
+
+    for variant in thumbnails.iter().chain(responsive.iter()) {
+        let options = PutObjectOptions {
+            content_type: Some(variant.format.content_type().to_string()),
+            cache_control: config.cache_control.clone(),
+            ..Default::default()
+        };
+        store
+            .put(&variant.key, variant.bytes.clone(), &options)
+            .await
+            .map_err(|err| ImageProcessingError{message: format!("failed to put image variant {}: {}", variant.key, err)})?;
+    }
+
+    Ok(ImageVariantLinks {
+        links: ImgLinkUpdates {
+            thumbnail_image: thumbnails
+                .iter()
+                .find(|variant| variant.format == format)
+                .map(|variant| format!("{}{}", config.link_prefix, variant.key)),
+            images: vec![format!("{}{}", config.link_prefix, key)]
+        },
+        srcset: responsive
+            .iter()
+            .filter(|variant| variant.format == format)
+            .map(|variant| (variant.width, format!("{}{}", config.link_prefix, variant.key)))
+            .collect()
+    })
+}
+
+/// Builds a hut's links from what's actually stored under its prefix, for
+/// writing back with `ImgLinkUpdates::sql_update`. Like
+/// `generate_image_variants`, `images` links only the originals under
+/// `images/`, ordered by file name; responsive copies are left out. The
+/// thumbnail is the one generated from the first original
+/// that has one, preferring the format that sorts first by extension, then
+/// any thumbnail, then the first original itself.
+pub async fn scan_hut_image_links(store: &dyn ObjectStore, attributes: &S3PathAttributes, link_prefix: &str) -> Result<ImgLinkUpdates, ImageProcessingError> {
+    let prefix = hut_prefix(attributes).map_err(|err| ImageProcessingError{message: err.message})?;
+    let objects = store
+        .list(&prefix)
+        .await
+        .map_err(|err| ImageProcessingError{message: format!("failed to list {}: {}", prefix, err)})?;
+
+    let mut originals: Vec<(String, String)> = Vec::new();
+    let mut thumbnails: Vec<(String, String)> = Vec::new();
+    for key in objects.into_iter().map(|info| info.key) {
+        let Ok(ObjectKey::HutImage { variant, file_name, .. }) = ObjectKey::parse(&key) else {
+            continue;
+        };
+        match variant {
+            ImageVariant::Original => originals.push((file_name, key)),
+            ImageVariant::Thumbnail => thumbnails.push((file_name, key)),
+            ImageVariant::Responsive => {}
+        }
+    }
+    originals.sort();
+    thumbnails.sort();
+
+    let thumbnail = originals
+        .iter()
+        .find_map(|(file_name, _)| {
+            let (_, stem) = split_key(file_name);
+            thumbnails
+                .iter()
+                .find(|(thumbnail_name, _)| split_key(thumbnail_name).1 == stem)
+        })
+        .or_else(|| thumbnails.first())
+        .or_else(|| originals.first())
+        .map(|(_, key)| format!("{}{}", link_prefix, key));
+
+    Ok(ImgLinkUpdates {
+        thumbnail_image: thumbnail,
+        images: originals
+            .iter()
+            .map(|(_, key)| format!("{}{}", link_prefix, key))
+            .collect()
+    })
+}
+
+/// Promotes an approved trip report's quarantined uploads to
+/// `tripreports/{id}/images/`. Each upload goes through
+/// `validate_image_upload`, so only re-encoded images with EXIF handled per
+/// `config.exif` are made public, stored as `{stem}.{extension}` with a
+/// `-1`, `-2`, ... suffix when a different image already has that name.
+/// Uploads that fail validation are purged instead of promoted, and ones
+/// that couldn't be read or written stay in quarantine. `image_links` is
+/// always rewritten for what was promoted: links are matched by key suffix
+/// like `ImgLinkUpdates::relocate`, links to rejected uploads are dropped,
+/// and promoted images with no link get one under `link_prefix`.
+pub async fn promote_trip_report_images(
+    store: &dyn ObjectStore,
+    trip_report: &mut TripReport,
+    link_prefix: &str,
+    config: &ImageValidationConfig
+) -> Result<PromotionReport, ImageProcessingError> {
+    if !trip_report.approved {
+        return Err(ImageProcessingError{message: format!("trip report {} is not approved", trip_report.id)});
+    }
+    let quarantine_prefix = quarantined_trip_report_prefix(&trip_report.id)
+        .map_err(|err| ImageProcessingError{message: err.message})?;
+    let images_prefix = format!(
+        "{}{}/",
+        trip_report_prefix(&trip_report.id).map_err(|err| ImageProcessingError{message: err.message})?,
+        ImageVariant::Original.dir()
+    );
+
+    let quarantined = store
+        .list(&quarantine_prefix)
+        .await
+        .map_err(|err| ImageProcessingError{message: format!("failed to list {}: {}", quarantine_prefix, err)})?;
+    let mut existing: HashMap<String, Option<String>> = store
+        .list(&images_prefix)
+        .await
+        .map_err(|err| ImageProcessingError{message: format!("failed to list {}: {}", images_prefix, err)})?
+        .into_iter()
+        .map(|info| (info.key, info.e_tag))
+        .collect();
+
+    let mut report = PromotionReport::default();
+    let mut processed: Vec<String> = Vec::new();
+    for key in quarantined.into_iter().map(|info| info.key) {
+        let Ok(ObjectKey::QuarantinedTripReportImage { file_name, .. }) = ObjectKey::parse(&key) else {
+            report.rejected.push(PromotionFailure { key: key.clone(), message: "not a quarantined trip report image key".to_string() });
+            processed.push(key);
+            continue;
+        };
+        let bytes = match store.get(&key).await {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => continue,
+            Err(err) => {
+                report.failed.push(PromotionFailure { key, message: err.message });
+                continue;
+            }
+        };
+
+        let owned_config = config.clone();
+        let validated = tokio::task::spawn_blocking(move || validate_image_upload(&bytes, &owned_config))
+            .await
+            .map_err(|err| ImageProcessingError{message: format!("image processing task failed: {}", err)})?;
+        let validated = match validated {
+            Ok(validated) => validated,
+            Err(err) => {
+                report.rejected.push(PromotionFailure { key: key.clone(), message: err.message });
+                processed.push(key);
+                continue;
+            }
+        };
+
+        let e_tag = e_tag(&validated.bytes);
+        let new_key = promoted_key(&trip_report.id, &file_name, validated.format, &e_tag, &existing)?;
+        if existing.get(&new_key) != Some(&Some(e_tag.clone())) {
+            let options = PutObjectOptions {
+                content_type: Some(validated.format.content_type().to_string()),
+                ..Default::default()
+            };
+            if let Err(err) = store.put(&new_key, validated.bytes, &options).await {
+                report.failed.push(PromotionFailure { key, message: err.message });
+                continue;
+            }
+        }
+        existing.insert(new_key.clone(), Some(e_tag));
+        report.promoted.insert(key.clone(), new_key);
+        processed.push(key);
+    }
+
+    for key in processed {
+        if let Err(err) = store.delete(&key).await {
+            report.delete_failures.push(DeleteObjectFailure { key, code: None, message: Some(err.message) });
+        }
+    }
+
+    let mut image_links = ImgLinkUpdates {
+        thumbnail_image: None,
+        images: std::mem::take(&mut trip_report.image_links)
+    }
+        .relocate(&report.promoted)
+        .images;
+    image_links.retain(|link| !report.rejected.iter().any(|rejected| link.ends_with(rejected.key.as_str())));
+    let mut unlinked: Vec<&String> = report.promoted
+        .values()
+        .filter(|new_key| !image_links.iter().any(|link| link.ends_with(new_key.as_str())))
+        .collect();
+    unlinked.sort();
+    unlinked.dedup();
+    image_links.extend(unlinked.into_iter().map(|new_key| format!("{}{}", link_prefix, new_key)));
+    trip_report.image_links = image_links;
+
+    Ok(report)
+}
+
+/// Public key for a promoted upload. An identical image already at a name
+/// keeps it, so a retried promotion doesn't duplicate images.
+fn promoted_key(
+    trip_report_id: &str,
+    file_name: &str,
+    format: ImageOutputFormat,
+    e_tag: &str,
+    existing: &HashMap<String, Option<String>>
+) -> Result<String, ImageProcessingError> {
+    let (_, stem) = split_key(file_name);
+    let mut suffix = 0;
+    loop {
+        let file_name = match suffix {
+            0 => format!("{}.{}", stem, format.extension()),
+            _ => format!("{}-{}.{}", stem, suffix, format.extension())
+        };
+        let key = ObjectKey::TripReportImage {
+            trip_report_id: trip_report_id.to_string(),
+            variant: ImageVariant::Original,
+            file_name
+        }
+            .to_key()
+            .map_err(|err| ImageProcessingError{message: err.message})?;
+        match existing.get(&key) {
+            Some(existing_e_tag) if existing_e_tag.as_deref() != Some(e_tag) => suffix += 1,
+            _ => return Ok(key)
+        }
+    }
+}
+
+/// Thumbnail key for an original image. Originals in the canonical layout
+/// get theirs in the matching `thumbnails` directory, e.g.
+/// `huts/co/10th/janet/thumbnails/photo.jpg`; any other key gets a
+/// `thumbnails` directory next to it.
+pub fn thumbnail_key(original_key: &str, format: ImageOutputFormat) -> String {
+    let (_, stem) = split_key(original_key);
+    variant_key(original_key, ImageVariant::Thumbnail, &format!("{}.{}", stem, format.extension()))
+}
+
+/// Key of a resized copy of an original image, e.g.
+/// `huts/co/10th/janet/responsive/photo-1280w.jpg`.
+pub fn responsive_key(original_key: &str, width: u32, format: ImageOutputFormat) -> String {
+    let (_, stem) = split_key(original_key);
+    variant_key(original_key, ImageVariant::Responsive, &format!("{}-{}w.{}", stem, width, format.extension()))
+}
+
+fn variant_key(original_key: &str, variant: ImageVariant, file_name: &str) -> String {
+    ObjectKey::parse(original_key)
+        .ok()
+        .and_then(|key| key.with_variant(variant, file_name))
+        .and_then(|key| key.to_key().ok())
+        .unwrap_or_else(|| {
+            let (dir, _) = split_key(original_key);
+            format!("{}{}/{}", dir, variant.dir(), file_name)
+        })
+}
+
+fn split_key(key: &str) -> (&str, &str) {
+    let (dir, file_name) = match key.rfind('/') {
+        Some(idx) => key.split_at(idx + 1),
+        None => ("", key)
+    };
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+
+    (dir, stem)
+}
+
+pub fn decode_image(bytes: &[u8]) -> Result<DynamicImage, ImageProcessingError> {
+    let reader = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|err| ImageProcessingError{message: format!("failed to read image: {}", err)})?;
+    let (image, _) = decode_oriented(reader)?;
+
+    Ok(image)
+}
+
+/// Checks an uploaded image before it's stored: the format by magic bytes,
+/// the encoded size and the dimensions, which are read from the header so
+/// oversized images are rejected before decoding. The image is then
+/// re-encoded in `config.output_format` with EXIF handled per
+/// `config.exif`. HEIC, HEIF and AVIF are recognised but rejected with a
+/// message asking for a JPEG or PNG: converting them would need a libheif
+/// or dav1d binding, which is deliberately left out of this crate.
+pub fn validate_image_upload(bytes: &[u8], config: &ImageValidationConfig) -> Result<ValidatedImage, ImageProcessingError> {
+    if bytes.is_empty() {
+        return Err(ImageProcessingError{message: "image is empty".to_string()});
+    }
+    if bytes.len() > config.max_size {
+        return Err(ImageProcessingError{message: format!("image is {} bytes, the limit is {}", bytes.len(), config.max_size)});
+    }
+    let format = match sniff_content_type(bytes) {
+        Some("image/jpeg") => ImageFormat::Jpeg,
+        Some("image/png") => ImageFormat::Png,
+        Some("image/gif") => ImageFormat::Gif,
+        Some("image/webp") => ImageFormat::WebP,
+        Some(content_type @ ("image/heic" | "image/heif" | "image/avif")) => {
+            return Err(ImageProcessingError{message: format!("{} images are not supported, upload a JPEG or PNG instead", content_type)});
+        },
+        Some(content_type) => return Err(ImageProcessingError{message: format!("{} is not an image", content_type)}),
+        None => return Err(ImageProcessingError{message: "unrecognized image format".to_string()})
+    };
+
+    let (width, height) = ImageReader::with_format(Cursor::new(bytes), format)
+        .into_dimensions()
+        .map_err(|err| ImageProcessingError{message: format!("failed to read image dimensions: {}", err)})?;
+    if width > config.max_width || height > config.max_height {
+        return Err(ImageProcessingError{message: format!(
+            "image is {}x{}, the limit is {}x{}",
+            width,
+            height,
+            config.max_width,
+            config.max_height
+        )});
+    }
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(config.max_width);
+    limits.max_image_height = Some(config.max_height);
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+    let (image, exif) = decode_oriented(reader)?;
+
+    let exif = match config.exif {
+        ExifPolicy::Strip => None,
+        ExifPolicy::KeepWithoutGps => exif.and_then(|mut exif| {
+            let _ = Orientation::remove_from_exif_chunk(&mut exif);
+            strip_exif_gps(&mut exif).map(|_| exif)
+        })
+    };
+
+    Ok(ValidatedImage {
+        bytes: encode_image_with_exif(&image, config.output_format, config.jpeg_quality, exif)?,
+        format: config.output_format,
+        width: image.width(),
+        height: image.height()
+    })
+}
+
+/// Decodes with EXIF orientation applied, returning the raw EXIF block too.
+fn decode_oriented<R>(reader: ImageReader<R>) -> Result<(DynamicImage, Option<Vec<u8>>), ImageProcessingError> where R: std::io::BufRead + std::io::Seek {
+    let mut decoder = reader
+        .into_decoder()
+        .map_err(|err| ImageProcessingError{message: format!("unsupported image: {}", err)})?;
+    let exif = decoder
+        .exif_metadata()
+        .map_err(|err| ImageProcessingError{message: format!("failed to read image metadata: {}", err)})?;
+    let orientation = decoder
+        .orientation()
+        .map_err(|err| ImageProcessingError{message: format!("failed to read image orientation: {}", err)})?;
+    let mut image = DynamicImage::from_decoder(decoder)
+        .map_err(|err| ImageProcessingError{message: format!("failed to decode image: {}", err)})?;
+    image.apply_orientation(orientation);
+
+    Ok((image, exif))
+}
+
+pub fn encode_image(image: &DynamicImage, format: ImageOutputFormat, jpeg_quality: u8) -> Result<Vec<u8>, ImageProcessingError> {
+    encode_image_with_exif(image, format, jpeg_quality, None)
+}
+
+fn encode_image_with_exif(image: &DynamicImage, format: ImageOutputFormat, jpeg_quality: u8, exif: Option<Vec<u8>>) -> Result<Vec<u8>, ImageProcessingError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let result = match format {
+        // the webp encoder only supports lossless rgb(a)8
+        ImageOutputFormat::WebP => {
+            let mut encoder = WebPEncoder::new_lossless(&mut bytes);
+            if let Some(exif) = exif {
+                let _ = encoder.set_exif_metadata(exif);
+            }
+            image.to_rgba8().write_with_encoder(encoder)
+        },
+        // jpeg has no alpha channel
+        ImageOutputFormat::Jpeg => {
+            let mut encoder = JpegEncoder::new_with_quality(&mut bytes, jpeg_quality);
+            if let Some(exif) = exif {
+                let _ = encoder.set_exif_metadata(exif);
+            }
+            image.to_rgb8().write_with_encoder(encoder)
+        }
+    };
+    result.map_err(|err| ImageProcessingError{message: format!("failed to encode {}: {}", format.extension(), err)})?;
+
+    Ok(bytes)
+}
+
+const EXIF_GPS_IFD_TAG: u16 = 0x8825;
+
+/// Blanks the GPS IFD of a raw TIFF-format EXIF block in place: every GPS
+/// entry and the values they point to are zeroed and the entry count set to
+/// zero. `None` if the block is malformed, in which case it shouldn't be
+/// kept at all.
+fn strip_exif_gps(exif: &mut [u8]) -> Option<()> {
+    let big_endian = match exif.get(..2)? {
+        b"MM" => true,
+        b"II" => false,
+        _ => return None
+    };
+    let read_u16 = |exif: &[u8], offset: usize| -> Option<u16> {
+        let bytes: [u8; 2] = exif.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let read_u32 = |exif: &[u8], offset: usize| -> Option<usize> {
+        let bytes: [u8; 4] = exif.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) } as usize)
+    };
+
+    let ifd0 = read_u32(exif, 4)?;
+    let mut gps_ifd: Option<usize> = None;
+    for i in 0..read_u16(exif, ifd0)? as usize {
+        let entry = ifd0 + 2 + i * 12;
+        if read_u16(exif, entry)? == EXIF_GPS_IFD_TAG {
+            gps_ifd = Some(read_u32(exif, entry + 8)?);
+        }
+    }
+    let Some(gps_ifd) = gps_ifd else {
+        return Some(());
+    };
+
+    let entry_count = read_u16(exif, gps_ifd)? as usize;
+    for i in 0..entry_count {
+        let entry = gps_ifd + 2 + i * 12;
+        let value_size = match read_u16(exif, entry + 2)? {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None
+        };
+        let size = value_size * read_u32(exif, entry + 4)?;
+        if size > 4 {
+            let offset = read_u32(exif, entry + 8)?;
+            exif.get_mut(offset..offset.checked_add(size)?)?.fill(0);
+        }
+        exif.get_mut(entry..entry + 12)?.fill(0);
+    }
+    exif.get_mut(gps_ifd..gps_ifd + 2)?.fill(0);
+
+    Some(())
+}
+
+fn encode_variants(key: &str, original: &[u8], config: &ImagePipelineConfig) -> Result<(Vec<EncodedVariant>, Vec<EncodedVariant>), ImageProcessingError> {
+    let image = decode_image(original)?;
+
+    let thumbnail = image.resize_to_fill(config.thumbnail_width, config.thumbnail_height, FilterType::Lanczos3);
+    let mut thumbnails: Vec<EncodedVariant> = Vec::new();
+    for format in &config.formats {
+        thumbnails.push(EncodedVariant {
+            key: thumbnail_key(key, *format),
+            width: thumbnail.width(),
+            format: *format,
+            bytes: encode_image(&thumbnail, *format, config.jpeg_quality)?
+        });
+    }
+
+    let mut widths: Vec<u32> = config.widths
+        .iter()
+        .copied()
+        .filter(|width| *width > 0 && *width < image.width())
+        .collect();
+    widths.sort_unstable();
+    widths.dedup();
+
+    let mut responsive: Vec<EncodedVariant> = Vec::new();
+    for width in widths {
+        let resized = image.resize(width, u32::MAX, FilterType::Lanczos3);
+        for format in &config.formats {
+            responsive.push(EncodedVariant {
+                key: responsive_key(key, width, *format),
+                width,
+                format: *format,
+                bytes: encode_image(&resized, *format, config.jpeg_quality)?
+            });
+        }
+    }
+
+    Ok((thumbnails, responsive))
+}
+
+#[derive(Debug)]
+pub struct ImageProcessingError {
+    pub message: String
+}
+
+impl fmt::Display for ImageProcessingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ImageProcessingError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GPS_IFD_OFFSET: usize = 56;
+
+    /// IFD0 with a Make and a GPS pointer, then a GPS IFD with an inline
+    /// latitude ref and out of line latitude and altitude, laid out so
+    /// everything from `GPS_IFD_OFFSET` on belongs to GPS.
+    fn exif_with_gps(big_endian: bool) -> Vec<u8> {
+        let u16_bytes = |value: u16| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let u32_bytes = |value: u32| if big_endian { value.to_be_bytes() } else { value.to_le_bytes() };
+        let entry = |exif: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: [u8; 4]| {
+            exif.extend(u16_bytes(tag));
+            exif.extend(u16_bytes(kind));
+            exif.extend(u32_bytes(count));
+            exif.extend(value);
+        };
+
+        let mut exif: Vec<u8> = if big_endian { b"MM\0*".to_vec() } else { b"II*\0".to_vec() };
+        exif.extend(u32_bytes(8));
+        exif.extend(u16_bytes(3));
+        entry(&mut exif, 0x010F, 2, 6, u32_bytes(50));
+        entry(&mut exif, 0x0112, 3, 1, [u16_bytes(1), [0, 0]].concat().try_into().unwrap());
+        entry(&mut exif, EXIF_GPS_IFD_TAG, 4, 1, u32_bytes(GPS_IFD_OFFSET as u32));
+        exif.extend(u32_bytes(0));
+        exif.extend(b"Canon\0");
+        assert_eq!(exif.len(), GPS_IFD_OFFSET);
+
+        exif.extend(u16_bytes(3));
+        entry(&mut exif, 0x0001, 2, 2, *b"N\0\0\0");
+        entry(&mut exif, 0x0002, 5, 3, u32_bytes(98));
+        entry(&mut exif, 0x0006, 5, 1, u32_bytes(122));
+        exif.extend(u32_bytes(0));
+        assert_eq!(exif.len(), 98);
+        for value in [39, 1, 30, 1, 1234, 100, 3500, 1] {
+            exif.extend(u32_bytes(value));
+        }
+
+        exif
+    }
+
+    fn assert_gps_stripped(big_endian: bool) {
+        let mut exif = exif_with_gps(big_endian);
+        assert_eq!(strip_exif_gps(&mut exif), Some(()));
+        assert!(exif[GPS_IFD_OFFSET..].iter().all(|byte| *byte == 0));
+        assert_eq!(&exif[50..56], b"Canon\0");
+    }
+
+    #[test]
+    fn strips_gps_from_little_endian_exif() {
+        assert_gps_stripped(false);
+    }
+
+    #[test]
+    fn strips_gps_from_big_endian_exif() {
+        assert_gps_stripped(true);
+    }
+
+    #[test]
+    fn leaves_exif_without_gps_alone() {
+        let mut exif = exif_with_gps(false);
+        exif.truncate(GPS_IFD_OFFSET);
+        // point the GPS entry's tag somewhere harmless
+        exif[34..36].copy_from_slice(&0x9000u16.to_le_bytes());
+        let original = exif.clone();
+        assert_eq!(strip_exif_gps(&mut exif), Some(()));
+        assert_eq!(exif, original);
+    }
+
+    #[test]
+    fn rejects_malformed_exif() {
+        let mut bad_byte_order = exif_with_gps(false);
+        bad_byte_order[..2].copy_from_slice(b"XX");
+        assert_eq!(strip_exif_gps(&mut bad_byte_order), None);
+
+        let mut truncated = exif_with_gps(true);
+        truncated.truncate(GPS_IFD_OFFSET + 10);
+        assert_eq!(strip_exif_gps(&mut truncated), None);
+
+        let mut value_out_of_range = exif_with_gps(false);
+        value_out_of_range.truncate(100);
+        assert_eq!(strip_exif_gps(&mut value_out_of_range), None);
+
+        let mut unknown_type = exif_with_gps(false);
+        unknown_type[GPS_IFD_OFFSET + 4..GPS_IFD_OFFSET + 6].copy_from_slice(&99u16.to_le_bytes());
+        assert_eq!(strip_exif_gps(&mut unknown_type), None);
+
+        assert_eq!(strip_exif_gps(&mut []), None);
+    }
+
+    fn encoded_png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes: Vec<u8> = Vec::new();
+        DynamicImage::new_rgb8(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+
+        bytes
+    }
+
+    fn validation_error(bytes: &[u8], config: &ImageValidationConfig) -> String {
+        validate_image_upload(bytes, config).unwrap_err().message
+    }
+
+    #[test]
+    fn rejects_uploads_by_magic_bytes() {
+        let config = ImageValidationConfig::default();
+        let mut heic = vec![0, 0, 0, 24];
+        heic.extend(b"ftypheic");
+        heic.extend([0; 12]);
+
+        assert_eq!(validation_error(&heic, &config), "image/heic images are not supported, upload a JPEG or PNG instead");
+        assert_eq!(validation_error(b"%PDF-1.7 not a photo", &config), "application/pdf is not an image");
+        assert_eq!(validation_error(b"just some text", &config), "unrecognized image format");
+        assert_eq!(validation_error(&[], &config), "image is empty");
+    }
+
+    #[test]
+    fn rejects_uploads_over_the_size_limit() {
+        let png = encoded_png(4, 4);
+        let config = ImageValidationConfig {
+            max_size: png.len() - 1,
+            ..ImageValidationConfig::default()
+        };
+
+        assert!(validation_error(&png, &config).starts_with(&format!("image is {} bytes", png.len())));
+    }
+
+    #[test]
+    fn rejects_uploads_over_the_dimension_limit() {
+        let config = ImageValidationConfig {
+            max_width: 16,
+            max_height: 16,
+            ..ImageValidationConfig::default()
+        };
+
+        assert_eq!(validation_error(&encoded_png(17, 4), &config), "image is 17x4, the limit is 16x16");
+        assert_eq!(validation_error(&encoded_png(4, 17), &config), "image is 4x17, the limit is 16x16");
+        assert!(validate_image_upload(&encoded_png(16, 16), &config).is_ok());
+    }
+
+    #[test]
+    fn converts_png_uploads_to_jpeg() {
+        let validated = validate_image_upload(&encoded_png(12, 8), &ImageValidationConfig::default()).unwrap();
+
+        assert_eq!(validated.format, ImageOutputFormat::Jpeg);
+        assert_eq!((validated.width, validated.height), (12, 8));
+        assert_eq!(sniff_content_type(&validated.bytes), Some("image/jpeg"));
+        let decoded = decode_image(&validated.bytes).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (12, 8));
+    }
+
+    #[tokio::test]
+    async fn promotes_validated_trip_report_images() {
+        let store = crate::helpers::object_store::create_memory_object_store();
+        let options = PutObjectOptions::default();
+        store.put("quarantine/tripreports/tr1/summit.png", encoded_png(12, 8), &options).await.unwrap();
+        store.put("quarantine/tripreports/tr1/cabin.png", encoded_png(6, 6), &options).await.unwrap();
+        store.put("quarantine/tripreports/tr1/notes.png", b"%PDF-1.7".to_vec(), &options).await.unwrap();
+        store.put("quarantine/tripreports/tr1/nested/photo.png", encoded_png(4, 4), &options).await.unwrap();
+        // an unrelated image already has the name cabin.png would get
+        store.put("tripreports/tr1/images/cabin.jpg", b"other".to_vec(), &options).await.unwrap();
+        let mut trip_report = TripReport {
+            id: "tr1".to_string(),
+            first_name: "Janet".to_string(),
+            last_name: "Doe".to_string(),
+            trip_start_date: chrono::NaiveDate::from_ymd_opt(2024, 1, 5).unwrap(),
+            trip_end_date: chrono::NaiveDate::from_ymd_opt(2024, 1, 7).unwrap(),
+            weather_conditions: None,
+            hut_conditions: "cozy".to_string(),
+            riding_conditions: None,
+            approved: true,
+            image_links: vec![
+                "https://cdn/quarantine/tripreports/tr1/summit.png".to_string(),
+                "https://cdn/quarantine/tripreports/tr1/notes.png".to_string()
+            ]
+        };
+
+        let report = promote_trip_report_images(&store, &mut trip_report, "https://cdn/", &ImageValidationConfig::default())
+            .await
+            .unwrap();
+
+        assert_eq!(report.promoted, HashMap::from([
+            ("quarantine/tripreports/tr1/summit.png".to_string(), "tripreports/tr1/images/summit.jpg".to_string()),
+            ("quarantine/tripreports/tr1/cabin.png".to_string(), "tripreports/tr1/images/cabin-1.jpg".to_string())
+        ]));
+        let mut rejected: Vec<&str> = report.rejected.iter().map(|rejected| rejected.key.as_str()).collect();
+        rejected.sort();
+        assert_eq!(rejected, vec!["quarantine/tripreports/tr1/nested/photo.png", "quarantine/tripreports/tr1/notes.png"]);
+        assert!(report.failed.is_empty() && report.delete_failures.is_empty());
+        assert_eq!(trip_report.image_links, vec![
+            "https://cdn/tripreports/tr1/images/summit.jpg".to_string(),
+            "https://cdn/tripreports/tr1/images/cabin-1.jpg".to_string()
+        ]);
+
+        let promoted = store.get("tripreports/tr1/images/summit.jpg").await.unwrap().unwrap();
+        assert_eq!(sniff_content_type(&promoted), Some("image/jpeg"));
+        assert_eq!(store.get("tripreports/tr1/images/cabin.jpg").await.unwrap(), Some(b"other".to_vec()));
+        assert!(store.list("quarantine/").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn scans_hut_links_from_any_store() {
+        let store = crate::helpers::object_store::create_memory_object_store();
+        for key in [
+            "huts/co/10th/janet/images/b.jpg",
+            "huts/co/10th/janet/images/a.png",
+            "huts/co/10th/janet/thumbnails/b.jpg",
+            "huts/co/10th/janet/responsive/b-640w.jpg"
+        ] {
+            store.put(key, vec![0], &PutObjectOptions::default()).await.unwrap();
+        }
+        let attributes = S3PathAttributes {
+            sanitized_state: "co".to_string(),
+            sanitized_system: "10th".to_string(),
+            sanitized_name: "janet".to_string()
+        };
+
+        let links = scan_hut_image_links(&store, &attributes, "https://cdn/").await.unwrap();
+        assert_eq!(links.images, vec![
+            "https://cdn/huts/co/10th/janet/images/a.png".to_string(),
+            "https://cdn/huts/co/10th/janet/images/b.jpg".to_string()
+        ]);
+        assert_eq!(links.thumbnail_image.as_deref(), Some("https://cdn/huts/co/10th/janet/thumbnails/b.jpg"));
+    }
+}
@@ -0,0 +1,515 @@
+use std::{collections::{BTreeMap, HashMap}, io::{Read, Write}, path::{Path, PathBuf}, sync::{Arc, RwLock}};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use md5::{Digest, Md5};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+
+use super::{content_type::content_type_from_extension, s3_helper::{PutObjectOptions, S3Error, S3Helper, S3JsonError, S3ObjectInfo, S3ObjectMetadata}};
+
+/// The object operations our services need, so they can run against S3 in
+/// production and against a local directory or memory in development and
+/// tests.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+
+    async fn list(&self, prefix: &str) -> Result<Vec<S3ObjectInfo>, S3Error>;
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, S3Error>;
+
+    async fn put(&self, key: &str, body: Vec<u8>, options: &PutObjectOptions) -> Result<(), S3Error>;
+
+    async fn head(&self, key: &str) -> Result<Option<S3ObjectMetadata>, S3Error>;
+
+    /// Deleting a key that doesn't exist is not an error, as in S3.
+    async fn delete(&self, key: &str) -> Result<(), S3Error>;
+}
+
+#[async_trait]
+impl ObjectStore for S3Helper {
+
+    async fn list(&self, prefix: &str) -> Result<Vec<S3ObjectInfo>, S3Error> {
+        self.list_object_infos(prefix).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, S3Error> {
+        let Some(object) = self.get_object(key).await? else {
+            return Ok(None);
+        };
+        let bytes = self.read_object_bytes(object)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to read object {}: {}", key, err)})?;
+
+        Ok(Some(bytes.into_inner()))
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>, options: &PutObjectOptions) -> Result<(), S3Error> {
+        self.put_object_with_options(key, body, options)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to put object {}: {}", key, aws_sdk_s3::error::DisplayErrorContext(err))})
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<S3ObjectMetadata>, S3Error> {
+        self.head_object(key).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), S3Error> {
+        self.delete_object(key).await
+    }
+}
+
+/// Reads and deserializes a JSON object. Gzipped bodies, e.g. written by
+/// `put_json`, are recognised by their magic bytes and decompressed, since
+/// not every store keeps the content encoding.
+pub async fn get_json<T>(store: &dyn ObjectStore, key: &str) -> Result<T, S3JsonError> where T: DeserializeOwned {
+    let mut body = store
+        .get(key)
+        .await
+        .map_err(S3JsonError::Transport)?
+        .ok_or_else(|| S3JsonError::NotFound { key: key.to_string() })?;
+
+    if body.starts_with(&GZIP_MAGIC) {
+        let mut decompressed: Vec<u8> = Vec::new();
+        GzDecoder::new(body.as_slice())
+            .read_to_end(&mut decompressed)
+            .map_err(|err| S3JsonError::Parse { key: key.to_string(), message: format!("failed to decompress: {}", err) })?;
+        body = decompressed;
+    }
+
+    serde_json::from_slice(&body)
+        .map_err(|err| S3JsonError::Parse { key: key.to_string(), message: err.to_string() })
+}
+
+/// Serializes `value` as JSON, as `application/geo+json` for `.geojson`
+/// keys. With `gzip` the body is compressed and stored with a gzip
+/// content encoding.
+pub async fn put_json<T>(store: &dyn ObjectStore, key: &str, value: &T, gzip: bool, options: &PutObjectOptions) -> Result<(), S3JsonError> where T: Serialize {
+    let json = serde_json::to_vec(value)
+        .map_err(|err| S3JsonError::Encode { key: key.to_string(), message: format!("failed to serialize: {}", err) })?;
+    let mut options = options.clone();
+    if options.content_type.is_none() {
+        options.content_type = Some(match content_type_from_extension(key) {
+            Some(content_type) if content_type.starts_with("application/geo+json") => content_type.to_string(),
+            _ => "application/json".to_string()
+        });
+    }
+
+    let body = if gzip {
+        options.content_encoding = Some("gzip".to_string());
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder
+            .write_all(&json)
+            .and_then(|_| encoder.finish())
+            .map_err(|err| S3JsonError::Encode { key: key.to_string(), message: format!("failed to compress: {}", err) })?
+    } else {
+        json
+    };
+
+    store
+        .put(key, body, &options)
+        .await
+        .map_err(S3JsonError::Transport)
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1F, 0x8B];
+
+/// Headers and metadata kept alongside an object outside of S3.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct StoredAttributes {
+    content_type: Option<String>,
+    cache_control: Option<String>,
+    content_disposition: Option<String>,
+    content_encoding: Option<String>,
+    metadata: HashMap<String, String>,
+    /// Computed once on put, so listings don't have to read every body.
+    #[serde(default)]
+    e_tag: Option<String>
+}
+
+impl StoredAttributes {
+
+    fn from_options(key: &str, body: &[u8], options: &PutObjectOptions) -> Self {
+        Self {
+            content_type: Some(options.content_type_for(key, body)),
+            cache_control: options.cache_control.clone(),
+            content_disposition: options.content_disposition.clone(),
+            content_encoding: options.content_encoding.clone(),
+            metadata: options.metadata.clone(),
+            e_tag: Some(e_tag(body))
+        }
+    }
+
+    fn to_metadata(&self, key: &str, content_length: u64, last_modified: Option<DateTime<Utc>>) -> S3ObjectMetadata {
+        S3ObjectMetadata {
+            key: key.to_string(),
+            content_type: self.content_type.clone(),
+            content_length: Some(content_length as i64),
+            e_tag: self.e_tag.clone(),
+            last_modified,
+            cache_control: self.cache_control.clone(),
+            content_disposition: self.content_disposition.clone(),
+            content_encoding: self.content_encoding.clone(),
+            metadata: self.metadata.clone()
+        }
+    }
+}
+
+/// Quoted MD5 of the body, matching S3's ETag for single part uploads.
+pub fn e_tag(body: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(Md5::digest(body)))
+}
+
+/// `e_tag` of a file, read in chunks rather than all at once.
+async fn file_e_tag(path: &Path) -> std::io::Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut md5 = Md5::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        md5.update(&buf[..read]);
+    }
+
+    Ok(format!("\"{}\"", hex::encode(md5.finalize())))
+}
+
+const FS_ATTRIBUTES_DIR: &str = ".attributes";
+
+/// Stores objects as files under `root`, with keys mapped to relative
+/// paths. Headers and user metadata are kept in JSON files under
+/// `root/.attributes`.
+#[derive(Clone, Debug)]
+pub struct FsObjectStore {
+    pub root: PathBuf
+}
+
+pub fn create_fs_object_store(root: impl AsRef<Path>) -> FsObjectStore {
+    FsObjectStore {
+        root: root.as_ref().to_path_buf()
+    }
+}
+
+impl FsObjectStore {
+
+    fn object_path(&self, key: &str) -> Result<PathBuf, S3Error> {
+        let valid = !key.is_empty()
+            && !key.starts_with(FS_ATTRIBUTES_DIR)
+            && key.split('/').all(|component| !component.is_empty() && component != "." && component != ".." && !component.contains('\\'));
+        if !valid {
+            return Err(S3Error{message: format!("invalid key for filesystem object store: {:?}", key)});
+        }
+
+        Ok(self.root.join(key))
+    }
+
+    fn attributes_path(&self, key: &str) -> PathBuf {
+        self.root.join(FS_ATTRIBUTES_DIR).join(format!("{}.json", key))
+    }
+
+    async fn read_attributes(&self, key: &str) -> StoredAttributes {
+        match tokio::fs::read(self.attributes_path(key)).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => StoredAttributes::default()
+        }
+    }
+
+    async fn list_dir(&self, dir: PathBuf, results: &mut Vec<S3ObjectInfo>) -> Result<(), S3Error> {
+        let mut dirs: Vec<PathBuf> = vec![dir];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(S3Error{message: format!("failed to list {}: {}", dir.display(), err)})
+            };
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .map_err(|err| S3Error{message: format!("failed to list {}: {}", dir.display(), err)})? {
+                    let path = entry.path();
+                    let file_type = entry
+                        .file_type()
+                        .await
+                        .map_err(|err| S3Error{message: format!("failed to stat {}: {}", path.display(), err)})?;
+                    if file_type.is_dir() {
+                        if path != self.root.join(FS_ATTRIBUTES_DIR) {
+                            dirs.push(path);
+                        }
+                        continue;
+                    }
+
+                    let Some(key) = path
+                        .strip_prefix(&self.root)
+                        .ok()
+                        .and_then(|relative| relative.to_str())
+                        .map(|relative| relative.replace(std::path::MAIN_SEPARATOR, "/")) else {
+                            continue;
+                        };
+                    let metadata = entry
+                        .metadata()
+                        .await
+                        .map_err(|err| S3Error{message: format!("failed to stat {}: {}", path.display(), err)})?;
+                    // files that weren't put through the store have no
+                    // stored ETag, use head to compute one
+                    let e_tag = self.read_attributes(&key).await.e_tag;
+                    results.push(S3ObjectInfo {
+                        key,
+                        size: Some(metadata.len() as i64),
+                        e_tag,
+                        last_modified: metadata.modified().ok().map(DateTime::<Utc>::from)
+                    });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ObjectStore for FsObjectStore {
+
+    async fn list(&self, prefix: &str) -> Result<Vec<S3ObjectInfo>, S3Error> {
+        // walk from the deepest directory the prefix fully names
+        let start = match prefix.rfind('/') {
+            Some(idx) => self.object_path(&prefix[..idx])?,
+            None => self.root.clone()
+        };
+        let mut results: Vec<S3ObjectInfo> = Vec::new();
+        self.list_dir(start, &mut results).await?;
+        results.retain(|info| info.key.starts_with(prefix));
+        results.sort_by(|a, b| a.key.cmp(&b.key));
+
+        Ok(results)
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, S3Error> {
+        let path = self.object_path(key)?;
+        match tokio::fs::read(&path).await {
+            Ok(body) => Ok(Some(body)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(S3Error{message: format!("failed to read {}: {}", path.display(), err)})
+        }
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>, options: &PutObjectOptions) -> Result<(), S3Error> {
+        let path = self.object_path(key)?;
+        let attributes = StoredAttributes::from_options(key, &body, options);
+        let attributes_path = self.attributes_path(key);
+        for dir in [path.parent(), attributes_path.parent()].into_iter().flatten() {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|err| S3Error{message: format!("failed to create {}: {}", dir.display(), err)})?;
+        }
+
+        tokio::fs::write(&path, body)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to write {}: {}", path.display(), err)})?;
+        let attributes = serde_json::to_vec(&attributes)
+            .map_err(|err| S3Error{message: format!("failed to serialize attributes for {}: {}", key, err)})?;
+        tokio::fs::write(&attributes_path, attributes)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to write {}: {}", attributes_path.display(), err)})?;
+
+        Ok(())
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<S3ObjectMetadata>, S3Error> {
+        let path = self.object_path(key)?;
+        let metadata = match tokio::fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(S3Error{message: format!("failed to stat {}: {}", path.display(), err)})
+        };
+        let mut attributes = self.read_attributes(key).await;
+        if attributes.e_tag.is_none() {
+            attributes.e_tag = Some(file_e_tag(&path)
+                .await
+                .map_err(|err| S3Error{message: format!("failed to read {}: {}", path.display(), err)})?);
+        }
+        let modified = metadata.modified().ok().map(DateTime::<Utc>::from);
+
+        Ok(Some(attributes.to_metadata(key, metadata.len(), modified)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), S3Error> {
+        let path = self.object_path(key)?;
+        for path in [path, self.attributes_path(key)] {
+            match tokio::fs::remove_file(&path).await {
+                Ok(()) => {},
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {},
+                Err(err) => return Err(S3Error{message: format!("failed to delete {}: {}", path.display(), err)})
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct MemoryObject {
+    body: Vec<u8>,
+    attributes: StoredAttributes,
+    last_modified: DateTime<Utc>
+}
+
+/// Keeps objects in memory, shared between clones. Meant for tests.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryObjectStore {
+    objects: Arc<RwLock<BTreeMap<String, MemoryObject>>>
+}
+
+pub fn create_memory_object_store() -> MemoryObjectStore {
+    MemoryObjectStore::default()
+}
+
+impl MemoryObjectStore {
+
+    fn objects(&self) -> Result<std::sync::RwLockReadGuard<'_, BTreeMap<String, MemoryObject>>, S3Error> {
+        self.objects
+            .read()
+            .map_err(|_| S3Error{message: "memory object store lock poisoned".to_string()})
+    }
+
+    fn objects_mut(&self) -> Result<std::sync::RwLockWriteGuard<'_, BTreeMap<String, MemoryObject>>, S3Error> {
+        self.objects
+            .write()
+            .map_err(|_| S3Error{message: "memory object store lock poisoned".to_string()})
+    }
+}
+
+#[async_trait]
+impl ObjectStore for MemoryObjectStore {
+
+    async fn list(&self, prefix: &str) -> Result<Vec<S3ObjectInfo>, S3Error> {
+        Ok(self.objects()?
+            .range(prefix.to_string()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, object)| S3ObjectInfo {
+                key: key.clone(),
+                size: Some(object.body.len() as i64),
+                e_tag: object.attributes.e_tag.clone(),
+                last_modified: Some(object.last_modified)
+            })
+            .collect())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, S3Error> {
+        Ok(self.objects()?
+            .get(key)
+            .map(|object| object.body.clone()))
+    }
+
+    async fn put(&self, key: &str, body: Vec<u8>, options: &PutObjectOptions) -> Result<(), S3Error> {
+        let attributes = StoredAttributes::from_options(key, &body, options);
+        self.objects_mut()?.insert(key.to_string(), MemoryObject {
+            body,
+            attributes,
+            last_modified: Utc::now()
+        });
+
+        Ok(())
+    }
+
+    async fn head(&self, key: &str) -> Result<Option<S3ObjectMetadata>, S3Error> {
+        Ok(self.objects()?
+            .get(key)
+            .map(|object| object.attributes.to_metadata(key, object.body.len() as u64, Some(object.last_modified))))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), S3Error> {
+        self.objects_mut()?.remove(key);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> PutObjectOptions {
+        PutObjectOptions {
+            content_type: Some("text/plain".to_string()),
+            metadata: HashMap::from([("owner".to_string(), "tests".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    async fn assert_round_trips(store: &dyn ObjectStore) {
+        store.put("huts/a/images/one.txt", b"one".to_vec(), &options()).await.unwrap();
+        store.put("huts/a/images/two.txt", b"two".to_vec(), &options()).await.unwrap();
+        store.put("huts/ab/images/three.txt", b"three".to_vec(), &options()).await.unwrap();
+
+        assert_eq!(store.get("huts/a/images/one.txt").await.unwrap(), Some(b"one".to_vec()));
+        assert_eq!(store.get("huts/a/images/missing.txt").await.unwrap(), None);
+
+        let head = store.head("huts/a/images/two.txt").await.unwrap().unwrap();
+        assert_eq!(head.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(head.content_length, Some(3));
+        assert_eq!(head.e_tag, Some(e_tag(b"two")));
+        assert_eq!(head.metadata.get("owner").map(String::as_str), Some("tests"));
+        assert!(store.head("huts/a/images/missing.txt").await.unwrap().is_none());
+
+        let keys = |infos: Vec<S3ObjectInfo>| infos.into_iter().map(|info| info.key).collect::<Vec<String>>();
+        assert_eq!(keys(store.list("huts/a/").await.unwrap()), vec!["huts/a/images/one.txt", "huts/a/images/two.txt"]);
+        assert_eq!(keys(store.list("huts/a").await.unwrap()), vec![
+            "huts/a/images/one.txt",
+            "huts/a/images/two.txt",
+            "huts/ab/images/three.txt"
+        ]);
+        assert!(store.list("maps/").await.unwrap().is_empty());
+
+        store.delete("huts/a/images/one.txt").await.unwrap();
+        store.delete("huts/a/images/one.txt").await.unwrap();
+        assert_eq!(store.get("huts/a/images/one.txt").await.unwrap(), None);
+        assert_eq!(keys(store.list("huts/a/").await.unwrap()), vec!["huts/a/images/two.txt"]);
+    }
+
+    fn fs_store() -> FsObjectStore {
+        create_fs_object_store(std::env::temp_dir().join(format!("object-store-{}", uuid::Uuid::new_v4())))
+    }
+
+    #[tokio::test]
+    async fn memory_store_round_trips() {
+        assert_round_trips(&create_memory_object_store()).await;
+    }
+
+    #[tokio::test]
+    async fn fs_store_round_trips() {
+        let store = fs_store();
+        assert_round_trips(&store).await;
+        // attributes live beside the objects but are never listed
+        assert!(store.list("").await.unwrap().iter().all(|info| !info.key.starts_with(FS_ATTRIBUTES_DIR)));
+
+        std::fs::remove_dir_all(&store.root).unwrap();
+    }
+
+    #[test]
+    fn fs_store_rejects_keys_outside_root() {
+        let store = fs_store();
+        for key in ["", "../secret", "huts/../../secret", "huts/./a", "huts//a", "huts/a/", ".attributes/huts/a.json", "huts\\a"] {
+            assert!(store.object_path(key).is_err(), "{:?} should be rejected", key);
+        }
+        assert_eq!(store.object_path("huts/a/images/one.txt").unwrap(), store.root.join("huts/a/images/one.txt"));
+    }
+
+    #[tokio::test]
+    async fn json_round_trips_with_and_without_gzip() {
+        let store = create_memory_object_store();
+        let value = serde_json::json!({"zones": ["front-range", "vail"]});
+
+        put_json(&store, "maps/zones.geojson", &value, false, &PutObjectOptions::default()).await.unwrap();
+        let head = store.head("maps/zones.geojson").await.unwrap().unwrap();
+        assert!(head.content_type.unwrap().starts_with("application/geo+json"));
+        assert_eq!(get_json::<serde_json::Value>(&store, "maps/zones.geojson").await.unwrap(), value);
+
+        put_json(&store, "maps/zones.json", &value, true, &PutObjectOptions::default()).await.unwrap();
+        assert!(store.get("maps/zones.json").await.unwrap().unwrap().starts_with(&GZIP_MAGIC));
+        assert_eq!(get_json::<serde_json::Value>(&store, "maps/zones.json").await.unwrap(), value);
+
+        assert!(matches!(get_json::<serde_json::Value>(&store, "maps/missing.json").await, Err(S3JsonError::NotFound { .. })));
+    }
+}
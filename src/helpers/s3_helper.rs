@@ -1,6 +1,16 @@
-use std::{fmt, io::{Cursor, Write}};
+use std::{collections::{HashMap, HashSet}, fmt, io::{Cursor, ErrorKind, Write}, path::{Path, PathBuf}, pin::Pin, sync::Arc, task::{ready, Context, Poll}, time::Duration};
 
-use aws_sdk_s3::{error::SdkError, operation::{get_object::GetObjectOutput, list_objects_v2::ListObjectsV2Output, put_object::PutObjectError}, primitives::{ByteStream, ByteStreamError}, types::Object, Client};
+use aws_sdk_s3::{config::{retry::RetryConfig, timeout::TimeoutConfig, Region}, error::{DisplayErrorContext, SdkError}, presigning::{PresignedRequest, PresigningConfig}, operation::{get_object::GetObjectOutput, list_objects_v2::ListObjectsV2Output, put_object::PutObjectError}, primitives::{ByteStream, ByteStreamError}, types::{ChecksumMode, CompletedMultipartUpload, CompletedPart, Delete, MetadataDirective, Object, ObjectIdentifier}, Client};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use chrono::{DateTime, TimeDelta, Utc};
+use md5::Md5;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, io::{AsyncBufRead, AsyncRead, AsyncReadExt, ReadBuf}, task::{JoinError, JoinSet}};
+
+use crate::model::{object::S3PathAttributes, objectkey::{hut_prefix, quarantined_trip_report_prefix, quarantined_trip_reports_prefix, ObjectKey}};
+
+use super::content_type::{detect_content_type, extension_for_content_type};
 
 #[derive(Clone)]
 pub struct S3Helper {
@@ -9,43 +19,102 @@ pub struct S3Helper {
 }
 
 pub fn create_s3_helper(aws_config: &aws_config::SdkConfig, bucket: &str) -> S3Helper {
-    let s3_client: aws_sdk_s3::Client = aws_sdk_s3::Client::new(aws_config);
-    S3Helper {
-        s3_client,
-        bucket: bucket.to_string()
+    S3Helper::builder(aws_config, bucket).build()
+}
+
+/// Overrides on top of a shared `SdkConfig` for one helper, e.g. an
+/// `endpoint_url` and `force_path_style(true)` to point it at MinIO or
+/// another S3-compatible server in integration tests.
+pub struct S3HelperBuilder {
+    config: aws_sdk_s3::config::Builder,
+    bucket: String
+}
+
+impl S3HelperBuilder {
+
+    pub fn endpoint_url(mut self, endpoint_url: impl Into<String>) -> Self {
+        self.config.set_endpoint_url(Some(endpoint_url.into()));
+        self
+    }
+
+    /// Addresses objects as `{endpoint}/{bucket}/{key}` rather than with
+    /// the bucket in the host name, which most local stand-ins require.
+    pub fn force_path_style(mut self, force_path_style: bool) -> Self {
+        self.config.set_force_path_style(Some(force_path_style));
+        self
+    }
+
+    pub fn region(mut self, region: impl Into<String>) -> Self {
+        self.config.set_region(Some(Region::new(region.into())));
+        self
+    }
+
+    pub fn retry_config(mut self, retry_config: RetryConfig) -> Self {
+        self.config.set_retry_config(Some(retry_config));
+        self
+    }
+
+    pub fn timeout_config(mut self, timeout_config: TimeoutConfig) -> Self {
+        self.config.set_timeout_config(Some(timeout_config));
+        self
+    }
+
+    pub fn build(self) -> S3Helper {
+        S3Helper {
+            s3_client: Client::from_conf(self.config.build()),
+            bucket: self.bucket
+        }
     }
 }
 
 impl S3Helper {
 
+    /// Starts from `aws_config`, like `create_s3_helper`, with the endpoint,
+    /// addressing style, region, retries and timeouts open to override.
+    pub fn builder(aws_config: &aws_config::SdkConfig, bucket: &str) -> S3HelperBuilder {
+        S3HelperBuilder {
+            config: aws_sdk_s3::config::Builder::from(aws_config),
+            bucket: bucket.to_string()
+        }
+    }
+
+    /// Keys of every object under `prefix`, following continuation tokens
+    /// past the 1000 keys a single `list_objects_v2` call returns. Folder
+    /// placeholder keys ending in `/` are skipped.
     pub async fn list_objects(
         &self,
         prefix: &str
-    ) -> Vec<String> {
-        let mut results: Vec<String> = Vec::new();
-        let loo: ListObjectsV2Output = match self.s3_client
-            .list_objects_v2()
-            .bucket(&self.bucket)
-            .prefix(prefix)
-            .send()
-            .await {
-                Err(_) => {
-                    return results;
-                },
-                Ok(res) => res 
-            };
-        let objects: &[Object] = loo
-            .contents();
+    ) -> Result<Vec<String>, S3Error> {
+        Ok(self.list_object_infos(prefix)
+            .await?
+            .into_iter()
+            .map(|info| info.key)
+            .collect())
+    }
 
-        for object in objects {
-            let key = object.key().map_or("", |key| key);
-            if !key.is_empty() && !key.ends_with("/") {
-                results.push(key.to_string());
-            }
+    /// Like `list_objects`, with each key's size, ETag and last modified time.
+    pub async fn list_object_infos(
+        &self,
+        prefix: &str
+    ) -> Result<Vec<S3ObjectInfo>, S3Error> {
+        let mut results: Vec<S3ObjectInfo> = Vec::new();
+        let mut pages = self.list_object_pages(prefix);
+        while let Some(page) = pages.next_page().await {
+            results.extend(page?);
         }
 
-        return results;
-    }   
+        Ok(results)
+    }
+
+    /// Lazily lists the objects under `prefix` one page at a time.
+    pub fn list_object_pages(&self, prefix: &str) -> S3ObjectPages<'_> {
+        S3ObjectPages {
+            s3_helper: self,
+            prefix: prefix.to_string(),
+            continuation_token: None,
+            done: false
+        }
+    }
 
     pub async fn get_object(&self, key: &str) -> Result<Option<GetObjectOutput>, S3Error> {
         let maybe_object: Option<GetObjectOutput> = match self.s3_client
@@ -70,6 +139,50 @@ impl S3Helper {
         Ok(maybe_object)
     }
 
+    /// Gets `key` only if it changed, per `conditions`, optionally reading
+    /// just a byte range of it. Callers caching by ETag pass the one they
+    /// have as `if_none_match`.
+    pub async fn get_object_conditional(&self, key: &str, conditions: &GetObjectConditions) -> Result<ConditionalGetOutput, S3Error> {
+        let result = self.s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_if_none_match(conditions.if_none_match.clone())
+            .set_if_modified_since(conditions.if_modified_since.map(|since| aws_sdk_s3::primitives::DateTime::from_secs(since.timestamp())))
+            .set_range(conditions.range.map(|range| range.to_header()))
+            .send()
+            .await;
+
+        match result {
+            Ok(object) => Ok(ConditionalGetOutput::Modified(Box::new(object))),
+            Err(err) => {
+                let status = err.raw_response().map(|response| response.status().as_u16());
+                let err_msg = format!("failed to get object {}: {}", key, DisplayErrorContext(&err));
+                if status == Some(304) {
+                    Ok(ConditionalGetOutput::NotModified)
+                } else if err.into_service_error().is_no_such_key() {
+                    Ok(ConditionalGetOutput::NotFound)
+                } else {
+                    Err(S3Error{message: err_msg})
+                }
+            }
+        }
+    }
+
+    /// Reads `range` of `key`, or `None` if it doesn't exist. The returned
+    /// object's `content_range` says which bytes were actually returned.
+    pub async fn get_object_range(&self, key: &str, range: ByteRange) -> Result<Option<GetObjectOutput>, S3Error> {
+        let conditions = GetObjectConditions {
+            range: Some(range),
+            ..Default::default()
+        };
+        match self.get_object_conditional(key, &conditions).await? {
+            ConditionalGetOutput::Modified(object) => Ok(Some(*object)),
+            ConditionalGetOutput::NotFound => Ok(None),
+            ConditionalGetOutput::NotModified => Err(S3Error{message: format!("unexpected not modified response for {}", key)})
+        }
+    }
+
     pub async fn read_object_bytes(&self, mut object: GetObjectOutput) -> Result<Cursor<Vec<u8>>, ByteStreamError> {
         let mut mem = Cursor::new(Vec::new());
         while let Some(bytes) = object.body.try_next().await?  {
@@ -79,37 +192,1287 @@ impl S3Helper {
         Ok(mem)
     }
 
+    /// Opens `key` for streaming instead of buffering the whole body like
+    /// `read_object_bytes`. The reader fails with `InvalidData` once more
+    /// than `options.max_size` bytes are read, and objects that declare a
+    /// larger length are refused up front.
+    pub async fn get_object_reader(&self, key: &str, options: &ObjectStreamOptions) -> Result<Option<S3ObjectReader>, S3Error> {
+        let Some(object) = self.get_object(key).await? else {
+            return Ok(None);
+        };
+        let total = object.content_length.and_then(|length| u64::try_from(length).ok());
+        if let (Some(total), Some(max_size)) = (total, options.max_size) {
+            if total > max_size {
+                return Err(S3Error{message: format!("{} is {} bytes, the limit is {}", key, total, max_size)});
+            }
+        }
+
+        Ok(Some(S3ObjectReader {
+            inner: Box::pin(object.body.into_async_read()),
+            transferred: 0,
+            total,
+            options: options.clone()
+        }))
+    }
+
+    /// Streams `key` to `path`, returning the number of bytes written or
+    /// `None` if the object doesn't exist. The body is written to a
+    /// temporary file next to `path` and renamed once complete, so a failed
+    /// or oversized download never leaves a partial file at `path`.
+    pub async fn download_object_to_file(&self, key: &str, path: impl AsRef<Path>, options: &ObjectStreamOptions) -> Result<Option<u64>, S3Error> {
+        let path = path.as_ref();
+        let Some(mut reader) = self.get_object_reader(key, options).await? else {
+            return Ok(None);
+        };
+
+        let mut temp_path = path.as_os_str().to_owned();
+        temp_path.push(".part");
+        let temp_path = PathBuf::from(temp_path);
+        let result = async {
+            let mut file = File::create(&temp_path).await?;
+            let written = tokio::io::copy(&mut reader, &mut file).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&temp_path, path).await?;
+            Ok::<u64, std::io::Error>(written)
+        }.await;
+
+        match result {
+            Ok(written) => Ok(Some(written)),
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&temp_path).await;
+                Err(S3Error{message: format!("failed to download {} to {}: {}", key, path.display(), err)})
+            }
+        }
+    }
+
+    /// Puts `body` with its content type detected from its leading bytes or
+    /// the key's extension.
     pub async fn put_object(
         &self, 
         key: &str, 
         body: Vec<u8>
     ) -> Result<(), SdkError<PutObjectError>> {
+        self.put_object_with_options(key, body, &PutObjectOptions::default()).await
+    }    
+
+    pub async fn put_object_with_options(
+        &self,
+        key: &str,
+        body: Vec<u8>,
+        options: &PutObjectOptions
+    ) -> Result<(), SdkError<PutObjectError>> {
+        let content_type = options.content_type_for(key, &body);
         self.s3_client
             .put_object()
             .bucket(&self.bucket)
             .key(key)
+            .content_type(content_type)
+            .set_cache_control(options.cache_control.clone())
+            .set_content_disposition(options.content_disposition.clone())
+            .set_content_encoding(options.content_encoding.clone())
+            .set_metadata(options.user_metadata())
             .body(ByteStream::from(body))
             .send()
             .await?;
 
-        return Ok(());
-    }    
+        Ok(())
+    }
 
-    pub async fn exists(
-        &self,
-        key: &str
-    ) -> bool {
-        return match self.s3_client
+    /// Uploads `body` under its SHA-256 digest, see
+    /// `ObjectKey::ContentAddressed`, unless an object with that digest
+    /// already exists. The digest is sent as the S3 checksum so corrupted
+    /// uploads are rejected, and kept in the `sha256` user metadata.
+    pub async fn put_object_content_addressed(&self, body: Vec<u8>, options: &PutObjectOptions) -> Result<ContentAddressedUpload, S3Error> {
+        let digest = Sha256::digest(&body);
+        let sha256 = hex::encode(digest);
+        let content_type = options.content_type_for("", &body);
+        let key = ObjectKey::ContentAddressed {
+            sha256: sha256.clone(),
+            extension: extension_for_content_type(&content_type).map(|extension| extension.to_string())
+        }
+            .to_key()
+            .map_err(|err| S3Error{message: err.message})?;
+
+        if self.head_object(&key).await?.is_some() {
+            return Ok(ContentAddressedUpload { key, sha256, uploaded: false });
+        }
+
+        let mut metadata = options.metadata.clone();
+        metadata.insert(SHA256_METADATA_KEY.to_string(), sha256.clone());
+        self.s3_client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .content_type(content_type)
+            .set_cache_control(options.cache_control.clone())
+            .set_content_disposition(options.content_disposition.clone())
+            .set_content_encoding(options.content_encoding.clone())
+            .set_metadata(Some(metadata))
+            .checksum_sha256(BASE64_STANDARD.encode(digest))
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|err| S3Error{message: format!("failed to put object {}: {}", key, DisplayErrorContext(err))})?;
+
+        Ok(ContentAddressedUpload { key, sha256, uploaded: true })
+    }
+
+    /// Reads `key` and checks its SHA-256 against the S3 checksum, the
+    /// `sha256` user metadata or, for content-addressed keys, the key
+    /// itself. Objects with none of those are returned unverified.
+    pub async fn get_object_verified(&self, key: &str) -> Result<Option<Vec<u8>>, S3Error> {
+        let object = match self.s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .checksum_mode(ChecksumMode::Enabled)
+            .send()
+            .await {
+                Ok(object) => object,
+                Err(err) => {
+                    let err_msg = format!("failed to get object {}: {}", key, DisplayErrorContext(&err));
+                    if err.into_service_error().is_no_such_key() {
+                        return Ok(None);
+                    } else {
+                        return Err(S3Error{message: err_msg});
+                    }
+                }
+            };
+
+        let checksum = object.checksum_sha256()
+            .and_then(|checksum| BASE64_STANDARD.decode(checksum).ok())
+            .map(hex::encode);
+        let expected = checksum
+            .or_else(|| object.metadata().and_then(|metadata| metadata.get(SHA256_METADATA_KEY)).cloned())
+            .or_else(|| match ObjectKey::parse(key) {
+                Ok(ObjectKey::ContentAddressed { sha256, .. }) => Some(sha256),
+                _ => None
+            });
+        let body = self.read_object_bytes(object)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to read object {}: {}", key, err)})?
+            .into_inner();
+
+        if let Some(expected) = expected {
+            let actual = hex::encode(Sha256::digest(&body));
+            if !actual.eq_ignore_ascii_case(&expected) {
+                return Err(S3Error{message: format!("checksum mismatch for {}: expected sha256 {}, got {}", key, expected, actual)});
+            }
+        }
+
+        Ok(Some(body))
+    }
+
+    /// Object metadata from a head call, or `None` if `key` doesn't exist.
+    pub async fn head_object(&self, key: &str) -> Result<Option<S3ObjectMetadata>, S3Error> {
+        match self.s3_client
             .head_object()
             .bucket(&self.bucket)
             .key(key)
-            .send().await {
-                Err(_) => false,
-                Ok(_) => true
+            .send()
+            .await {
+                Ok(output) => Ok(Some(S3ObjectMetadata {
+                    key: key.to_string(),
+                    content_type: output.content_type,
+                    content_length: output.content_length,
+                    e_tag: output.e_tag,
+                    last_modified: output.last_modified.as_ref().and_then(to_chrono),
+                    cache_control: output.cache_control,
+                    content_disposition: output.content_disposition,
+                    content_encoding: output.content_encoding,
+                    metadata: output.metadata.unwrap_or_default()
+                })),
+                Err(err) => {
+                    let err_msg = format!("failed to head object {}: {}", key, DisplayErrorContext(&err));
+                    if err.into_service_error().is_not_found() {
+                        Ok(None)
+                    } else {
+                        Err(S3Error{message: err_msg})
+                    }
+                }
+            }
+    }
+
+    /// Uploads everything read from `reader`. Bodies smaller than
+    /// `config.threshold` go up in a single request, larger ones as a
+    /// multipart upload with up to `config.concurrency` parts in flight. A
+    /// failed multipart upload is aborted so no orphaned parts are left. The
+    /// config is validated up front, and a body that would need more than
+    /// 10,000 parts fails (and is aborted) once it reaches that many.
+    pub async fn put_object_stream<R>(
+        &self,
+        key: &str,
+        mut reader: R,
+        options: &PutObjectOptions,
+        config: &MultipartUploadConfig
+    ) -> Result<(), S3Error> where R: AsyncRead + Unpin {
+        config.validate(None)?;
+        let first = read_chunk(&mut reader, config.threshold.max(config.part_size))
+            .await
+            .map_err(|err| S3Error{message: format!("failed to read upload body for {}: {}", key, err)})?;
+        if first.len() < config.threshold.max(config.part_size) {
+            return self.put_object_with_options(key, first, options)
+                .await
+                .map_err(|err| S3Error{message: format!("failed to put object {}: {}", key, DisplayErrorContext(err))});
+        }
+
+        let upload_id = self.s3_client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(options.content_type_for(key, &first))
+            .set_cache_control(options.cache_control.clone())
+            .set_content_disposition(options.content_disposition.clone())
+            .set_content_encoding(options.content_encoding.clone())
+            .set_metadata(options.user_metadata())
+            .send()
+            .await
+            .map_err(|err| S3Error{message: format!("failed to create multipart upload for {}: {}", key, DisplayErrorContext(err))})?
+            .upload_id
+            .ok_or_else(|| S3Error{message: format!("no upload id returned for multipart upload of {}", key)})?;
+
+        match self.upload_parts(key, &upload_id, first, &mut reader, config).await {
+            Ok(parts) => {
+                self.s3_client
+                    .complete_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(key)
+                    .upload_id(&upload_id)
+                    .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+                    .send()
+                    .await
+                    .map_err(|err| S3Error{message: format!("failed to complete multipart upload for {}: {}", key, DisplayErrorContext(err))})?;
+
+                Ok(())
+            },
+            Err(err) => {
+                self.abort_multipart_upload(key, &upload_id).await;
+                Err(err)
+            }
+        }
+    }
+
+    /// Streams the file at `path` to `key`, see `put_object_stream`. Fails
+    /// before uploading anything if the file needs more than 10,000 parts.
+    pub async fn put_object_file(
+        &self,
+        key: &str,
+        path: impl AsRef<Path>,
+        options: &PutObjectOptions,
+        config: &MultipartUploadConfig
+    ) -> Result<(), S3Error> {
+        let file = File::open(path.as_ref())
+            .await
+            .map_err(|err| S3Error{message: format!("failed to open {}: {}", path.as_ref().display(), err)})?;
+        let size = file
+            .metadata()
+            .await
+            .map_err(|err| S3Error{message: format!("failed to read metadata of {}: {}", path.as_ref().display(), err)})?
+            .len();
+        config.validate(Some(size))?;
+
+        self.put_object_stream(key, file, options, config).await
+    }
+
+    async fn upload_parts<R>(
+        &self,
+        key: &str,
+        upload_id: &str,
+        first: Vec<u8>,
+        reader: &mut R,
+        config: &MultipartUploadConfig
+    ) -> Result<Vec<CompletedPart>, S3Error> where R: AsyncRead + Unpin {
+        let mut carry = first;
+        let mut in_flight: JoinSet<Result<CompletedPart, S3Error>> = JoinSet::new();
+        let mut parts: Vec<CompletedPart> = Vec::new();
+        let mut part_number = 0;
+        let mut eof = false;
+
+        loop {
+            // every part but the last must be exactly part_size
+            let mut body = if carry.len() > config.part_size {
+                let rest = carry.split_off(config.part_size);
+                std::mem::replace(&mut carry, rest)
+            } else {
+                std::mem::take(&mut carry)
+            };
+            if body.len() < config.part_size && !eof {
+                let wanted = config.part_size - body.len();
+                let more = read_chunk(reader, wanted)
+                    .await
+                    .map_err(|err| S3Error{message: format!("failed to read upload body for {}: {}", key, err)})?;
+                eof = more.len() < wanted;
+                body.extend(more);
+            }
+            if body.is_empty() {
+                break;
+            }
+
+            if in_flight.len() >= config.concurrency.max(1) {
+                if let Some(part) = in_flight.join_next().await {
+                    parts.push(flatten_join(part)?);
+                }
+            }
+
+            if part_number >= MAX_UPLOAD_PARTS {
+                return Err(S3Error{message: format!(
+                    "upload of {} needs more than {} parts of {} bytes", key, MAX_UPLOAD_PARTS, config.part_size
+                )});
+            }
+            part_number += 1;
+            let s3_client = self.s3_client.clone();
+            let bucket = self.bucket.clone();
+            let key = key.to_string();
+            let upload_id = upload_id.to_string();
+            in_flight.spawn(async move {
+                let output = s3_client
+                    .upload_part()
+                    .bucket(bucket)
+                    .key(&key)
+                    .upload_id(upload_id)
+                    .part_number(part_number)
+                    .body(ByteStream::from(body))
+                    .send()
+                    .await
+                    .map_err(|err| S3Error{message: format!("failed to upload part {} of {}: {}", part_number, key, DisplayErrorContext(err))})?;
+
+                Ok(CompletedPart::builder()
+                    .set_e_tag(output.e_tag)
+                    .part_number(part_number)
+                    .build())
+            });
+        }
+
+        while let Some(part) = in_flight.join_next().await {
+            parts.push(flatten_join(part)?);
+        }
+        parts.sort_by_key(|part| part.part_number());
+
+        Ok(parts)
+    }
+
+    async fn abort_multipart_upload(&self, key: &str, upload_id: &str) {
+        // best effort, the original error is more useful to the caller
+        let _ = self.s3_client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await;
+    }
+
+    pub async fn delete_object(&self, key: &str) -> Result<(), S3Error> {
+        self.s3_client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|err| S3Error{message: format!("failed to delete object {}: {}", key, DisplayErrorContext(err))})?;
+
+        Ok(())
+    }
+
+    /// Deletes `keys` with DeleteObjects in chunks of 1000. Keys S3 refuses
+    /// to delete are reported in the result rather than failing the batch.
+    pub async fn delete_objects(&self, keys: &[String]) -> Result<DeleteObjectsReport, S3Error> {
+        let mut report = DeleteObjectsReport::default();
+        for chunk in keys.chunks(DELETE_OBJECTS_MAX_KEYS) {
+            let objects = chunk
+                .iter()
+                .map(|key| ObjectIdentifier::builder().key(key).build())
+                .collect::<Result<Vec<ObjectIdentifier>, _>>()
+                .map_err(|err| S3Error{message: format!("failed to build delete request: {}", err)})?;
+            let delete = Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(false)
+                .build()
+                .map_err(|err| S3Error{message: format!("failed to build delete request: {}", err)})?;
+
+            let output = self.s3_client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(delete)
+                .send()
+                .await
+                .map_err(|err| S3Error{message: format!("failed to delete objects: {}", DisplayErrorContext(err))})?;
+
+            report.deleted.extend(output
+                .deleted()
+                .iter()
+                .filter_map(|deleted| deleted.key().map(|key| key.to_string())));
+            report.errors.extend(output
+                .errors()
+                .iter()
+                .map(|error| DeleteObjectFailure {
+                    key: error.key().unwrap_or_default().to_string(),
+                    code: error.code().map(|code| code.to_string()),
+                    message: error.message().map(|message| message.to_string())
+                }));
+        }
+
+        Ok(report)
+    }
+
+    /// Deletes every object under `prefix`, one listed page at a time.
+    /// `prefix` names a folder, so `huts/a` and `huts/a/` both delete
+    /// `huts/a/...` and leave siblings like `huts/ab/` alone. An empty prefix
+    /// is refused rather than emptying the bucket.
+    pub async fn delete_prefix(&self, prefix: &str) -> Result<DeleteObjectsReport, S3Error> {
+        let prefix = prefix.trim_end_matches('/');
+        if prefix.is_empty() {
+            return Err(S3Error{message: "refusing to delete objects under an empty prefix".to_string()});
+        }
+        let prefix = format!("{}/", prefix);
+
+        let mut report = DeleteObjectsReport::default();
+        let mut pages = self.list_object_pages(&prefix);
+        while let Some(page) = pages.next_page().await {
+            let keys: Vec<String> = page?
+                .into_iter()
+                .map(|info| info.key)
+                .collect();
+            let page_report = self.delete_objects(&keys).await?;
+            report.deleted.extend(page_report.deleted);
+            report.errors.extend(page_report.errors);
+        }
+
+        Ok(report)
+    }
+
+    /// Server-side copy of `source_key` to `destination_key`, preserving
+    /// the content type, headers and user metadata. Objects over the 5 GiB
+    /// CopyObject limit are copied part by part.
+    pub async fn copy_object(&self, source_key: &str, destination_key: &str) -> Result<(), S3Error> {
+        let metadata = self.head_object(source_key)
+            .await?
+            .ok_or_else(|| S3Error{message: format!("cannot copy {}, it does not exist", source_key)})?;
+        let size = metadata.content_length.unwrap_or_default();
+        if size <= MAX_COPY_OBJECT_SIZE {
+            self.s3_client
+                .copy_object()
+                .bucket(&self.bucket)
+                .key(destination_key)
+                .copy_source(self.copy_source(source_key))
+                .metadata_directive(MetadataDirective::Copy)
+                .send()
+                .await
+                .map_err(|err| S3Error{message: format!("failed to copy {} to {}: {}", source_key, destination_key, DisplayErrorContext(err))})?;
+
+            return Ok(());
+        }
+
+        // multipart copies don't carry metadata over, so set it from the head
+        let upload_id = self.s3_client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(destination_key)
+            .set_content_type(metadata.content_type.clone())
+            .set_cache_control(metadata.cache_control.clone())
+            .set_content_disposition(metadata.content_disposition.clone())
+            .set_content_encoding(metadata.content_encoding.clone())
+            .set_metadata(Some(metadata.metadata.clone()))
+            .send()
+            .await
+            .map_err(|err| S3Error{message: format!("failed to create multipart upload for {}: {}", destination_key, DisplayErrorContext(err))})?
+            .upload_id
+            .ok_or_else(|| S3Error{message: format!("no upload id returned for multipart upload of {}", destination_key)})?;
+
+        match self.copy_parts(source_key, destination_key, &upload_id, size).await {
+            Ok(parts) => {
+                self.s3_client
+                    .complete_multipart_upload()
+                    .bucket(&self.bucket)
+                    .key(destination_key)
+                    .upload_id(&upload_id)
+                    .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+                    .send()
+                    .await
+                    .map_err(|err| S3Error{message: format!("failed to complete multipart copy to {}: {}", destination_key, DisplayErrorContext(err))})?;
+
+                Ok(())
+            },
+            Err(err) => {
+                self.abort_multipart_upload(destination_key, &upload_id).await;
+                Err(err)
+            }
+        }
+    }
+
+    pub async fn move_object(&self, source_key: &str, destination_key: &str) -> Result<(), S3Error> {
+        self.copy_object(source_key, destination_key).await?;
+        self.delete_object(source_key).await
+    }
+
+    /// Moves every object under one hut's prefix to another's, e.g. after a
+    /// rename. See `move_objects` for what a partial failure leaves behind.
+    pub async fn relocate_hut_objects(&self, from: &S3PathAttributes, to: &S3PathAttributes) -> Result<RelocationReport, S3Error> {
+        let from_prefix = hut_prefix(from).map_err(|err| S3Error{message: err.message})?;
+        let to_prefix = hut_prefix(to).map_err(|err| S3Error{message: err.message})?;
+        if from_prefix == to_prefix {
+            return Ok(RelocationReport::default());
+        }
+
+        let relocations: HashMap<String, String> = self.list_objects(&from_prefix)
+            .await?
+            .into_iter()
+            .map(|key| {
+                let new_key = format!("{}{}", to_prefix, &key[from_prefix.len()..]);
+                (key, new_key)
+            })
+            .collect();
+        self.move_objects(relocations)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to relocate objects to {}: {}", to_prefix, err.message)})
+    }
+
+    /// Copies every key in `relocations` to its new key, then deletes the
+    /// originals in batches. A failed copy is an error and nothing has been
+    /// deleted yet, so the move can be retried. Once everything is copied
+    /// the objects count as moved: originals that fail to delete are listed
+    /// in `delete_failures` for cleanup instead of failing the move, so
+    /// callers still rewrite their links to the new keys.
+    async fn move_objects(&self, relocations: HashMap<String, String>) -> Result<RelocationReport, S3Error> {
+        for (key, new_key) in &relocations {
+            self.copy_object(key, new_key).await?;
+        }
+
+        let old_keys: Vec<String> = relocations.keys().cloned().collect();
+        let report = self.delete_objects(&old_keys).await?;
+
+        Ok(RelocationReport {
+            moved: relocations,
+            delete_failures: report.errors
+        })
+    }
+
+    async fn copy_parts(&self, source_key: &str, destination_key: &str, upload_id: &str, size: i64) -> Result<Vec<CompletedPart>, S3Error> {
+        let mut parts: Vec<CompletedPart> = Vec::new();
+        let mut start: i64 = 0;
+        let mut part_number = 0;
+        while start < size {
+            let end = (start + COPY_PART_SIZE).min(size) - 1;
+            part_number += 1;
+            let output = self.s3_client
+                .upload_part_copy()
+                .bucket(&self.bucket)
+                .key(destination_key)
+                .upload_id(upload_id)
+                .part_number(part_number)
+                .copy_source(self.copy_source(source_key))
+                .copy_source_range(format!("bytes={}-{}", start, end))
+                .send()
+                .await
+                .map_err(|err| S3Error{message: format!("failed to copy part {} of {}: {}", part_number, source_key, DisplayErrorContext(err))})?;
+
+            parts.push(CompletedPart::builder()
+                .set_e_tag(output.copy_part_result.and_then(|result| result.e_tag))
+                .part_number(part_number)
+                .build());
+            start = end + 1;
+        }
+
+        Ok(parts)
+    }
+
+    fn copy_source(&self, key: &str) -> String {
+        format!("{}/{}", self.bucket, encode_key(key))
+    }
+
+    /// Presigned GET for `key`, e.g. to show a private image in the browser.
+    pub async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<PresignedUrl, S3Error> {
+        let presigning_config = PresigningConfig::expires_in(expires_in)
+            .map_err(|err| S3Error{message: format!("invalid presigned url expiry: {}", err)})?;
+        let request = self.s3_client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(presigning_config)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to presign get for {}: {}", key, DisplayErrorContext(err))})?;
+
+        Ok(PresignedUrl::from_request(&request, expires_in))
+    }
+
+    /// Presigned PUT for `key`. The content type and length are signed, so
+    /// the upload is rejected unless the browser sends exactly the headers
+    /// returned alongside the url.
+    pub async fn presign_put(&self, key: &str, content_type: &str, content_length: i64, expires_in: Duration) -> Result<PresignedUrl, S3Error> {
+        let presigning_config = PresigningConfig::expires_in(expires_in)
+            .map_err(|err| S3Error{message: format!("invalid presigned url expiry: {}", err)})?;
+        let request = self.s3_client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(content_type)
+            .content_length(content_length)
+            .presigned(presigning_config)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to presign put for {}: {}", key, DisplayErrorContext(err))})?;
+
+        Ok(PresignedUrl::from_request(&request, expires_in))
+    }
+
+    /// Presigned PUT for a trip report image under its
+    /// `ObjectKey::QuarantinedTripReportImage` key, after checking the
+    /// declared type and size against `policy`.
+    pub async fn presign_trip_report_image_upload(
+        &self,
+        trip_report_id: &str,
+        file_name: &str,
+        content_type: &str,
+        content_length: i64,
+        policy: &PresignedUploadPolicy
+    ) -> Result<PresignedUrl, S3Error> {
+        if !policy.allowed_content_types.iter().any(|allowed| allowed.eq_ignore_ascii_case(content_type)) {
+            return Err(S3Error{message: format!("content type {} is not allowed for trip report images", content_type)});
+        }
+        if content_length <= 0 || content_length > policy.max_size {
+            return Err(S3Error{message: format!("trip report images must be between 1 and {} bytes, got {}", policy.max_size, content_length)});
+        }
+        let key = ObjectKey::QuarantinedTripReportImage {
+            trip_report_id: trip_report_id.to_string(),
+            file_name: file_name.to_string()
+        }
+            .to_key()
+            .map_err(|err| S3Error{message: err.message})?;
+
+        self.presign_put(&key, content_type, content_length, policy.expires_in).await
+    }
+
+    /// Deletes the quarantined images of a rejected trip report.
+    pub async fn purge_quarantined_trip_report_images(&self, trip_report_id: &str) -> Result<DeleteObjectsReport, S3Error> {
+        let prefix = quarantined_trip_report_prefix(trip_report_id)
+            .map_err(|err| S3Error{message: err.message})?;
+
+        self.delete_prefix(&prefix).await
+    }
+
+    /// Deletes quarantined images last modified more than `max_age` ago,
+    /// whatever trip report they belong to. Objects with no modification
+    /// time are kept.
+    pub async fn purge_stale_quarantined_images(&self, max_age: Duration) -> Result<DeleteObjectsReport, S3Error> {
+        let max_age = TimeDelta::from_std(max_age)
+            .map_err(|err| S3Error{message: format!("invalid quarantine max age: {}", err)})?;
+        let cutoff = Utc::now() - max_age;
+
+        let stale: Vec<String> = self.list_object_infos(&quarantined_trip_reports_prefix())
+            .await?
+            .into_iter()
+            .filter(|info| info.last_modified.is_some_and(|last_modified| last_modified < cutoff))
+            .map(|info| info.key)
+            .collect();
+
+        self.delete_objects(&stale).await
+    }
+
+    /// Whether `key` exists. Only a 404 counts as missing, so expired
+    /// credentials or a denied request come back as an error instead. Use
+    /// `head_object` for the size, ETag and metadata.
+    pub async fn exists(
+        &self,
+        key: &str
+    ) -> Result<bool, S3Error> {
+        Ok(self.head_object(key).await?.is_some())
+    }
+
+    /// Uploads the files under `local_dir` to `prefix`, skipping files whose
+    /// remote copy already matches, and optionally deleting remote objects
+    /// with no local file. `prefix` names a folder, so `maps` and `maps/`
+    /// both sync to `maps/`, and an empty prefix is refused. Per-file
+    /// failures are collected in the report instead of stopping the sync.
+    /// Symlinks aren't followed: each is reported as a failure, and any
+    /// remote object at its key is left alone rather than deleted as extra.
+    pub async fn sync_dir(&self, local_dir: impl AsRef<Path>, prefix: &str, options: &SyncOptions) -> Result<SyncReport, S3Error> {
+        // an empty prefix would make delete_extras empty the bucket, and
+        // without the trailing / sibling prefixes like `maps-archive/` match
+        let prefix = prefix.trim_end_matches('/');
+        if prefix.is_empty() {
+            return Err(S3Error{message: "refusing to sync to an empty prefix".to_string()});
+        }
+        let prefix = format!("{}/", prefix);
+
+        let local_dir = local_dir.as_ref();
+        let remote: HashMap<String, S3ObjectInfo> = self.list_object_infos(&prefix)
+            .await?
+            .into_iter()
+            .map(|info| (info.key.clone(), info))
+            .collect();
+
+        let mut report = SyncReport::default();
+        let mut local_keys: HashSet<String> = HashSet::new();
+        let (files, symlinks) = list_files(local_dir).await?;
+        for path in symlinks {
+            let key = local_key(local_dir, &path, &prefix).unwrap_or_else(|| path.display().to_string());
+            local_keys.insert(key.clone());
+            report.failed.push(SyncFailure {
+                key,
+                message: format!("{} is a symbolic link, which is not synced", path.display())
+            });
+        }
+        for path in files {
+            let Some(key) = local_key(local_dir, &path, &prefix) else {
+                report.failed.push(SyncFailure {
+                    key: path.display().to_string(),
+                    message: "path is not valid utf-8".to_string()
+                });
+                continue;
+            };
+            local_keys.insert(key.clone());
+
+            let local = match hash_file(&path).await {
+                Ok(local) => local,
+                Err(err) => {
+                    report.failed.push(SyncFailure { key, message: format!("failed to read {}: {}", path.display(), err) });
+                    continue;
+                }
+            };
+            match self.is_unchanged(&key, &local, remote.get(&key), options.comparison).await {
+                Ok(true) => {
+                    report.unchanged.push(key);
+                    continue;
+                },
+                Ok(false) => {},
+                Err(err) => {
+                    report.failed.push(SyncFailure { key, message: err.message });
+                    continue;
+                }
+            }
+
+            if !options.dry_run {
+                let mut put_options = options.put_options.clone();
+                put_options.metadata.insert(SHA256_METADATA_KEY.to_string(), local.sha256.clone());
+                if let Err(err) = self.put_object_file(&key, &path, &put_options, &options.multipart).await {
+                    report.failed.push(SyncFailure { key, message: err.message });
+                    continue;
+                }
+            }
+            report.uploaded.push(key);
+        }
+
+        if options.delete_extras {
+            let mut extras: Vec<String> = remote
+                .into_keys()
+                .filter(|key| !local_keys.contains(key))
+                .collect();
+            extras.sort();
+            if options.dry_run {
+                report.deleted = extras;
+            } else {
+                let delete_report = self.delete_objects(&extras).await?;
+                report.deleted = delete_report.deleted;
+                report.failed.extend(delete_report.errors
+                    .into_iter()
+                    .map(|error| SyncFailure {
+                        key: error.key,
+                        message: error.message.unwrap_or_else(|| "failed to delete".to_string())
+                    }));
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn is_unchanged(&self, key: &str, local: &LocalFileHash, remote: Option<&S3ObjectInfo>, comparison: SyncComparison) -> Result<bool, S3Error> {
+        let Some(remote) = remote else {
+            return Ok(false);
         };
+        if remote.size != Some(local.size as i64) {
+            return Ok(false);
+        }
+
+        // multipart ETags aren't an MD5 of the body, fall back to the hash
+        let e_tag = remote.e_tag.as_deref().unwrap_or_default().trim_matches('"');
+        if comparison == SyncComparison::SizeAndETag && !e_tag.contains('-') {
+            return Ok(e_tag.eq_ignore_ascii_case(&local.md5));
+        }
+
+        Ok(self.head_object(key)
+            .await?
+            .and_then(|metadata| metadata.metadata.get(SHA256_METADATA_KEY).cloned())
+            .is_some_and(|sha256| sha256.eq_ignore_ascii_case(&local.sha256)))
+    }
+}
+
+const SHA256_METADATA_KEY: &str = "sha256";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncComparison {
+    /// Compare sizes and the ETag against the local MD5, falling back to
+    /// the stored SHA-256 for multipart uploads.
+    SizeAndETag,
+    /// Compare sizes and the SHA-256 stored when the file was synced. Costs
+    /// a head request per file of matching size.
+    Sha256
+}
+
+#[derive(Clone, Debug)]
+pub struct SyncOptions {
+    pub comparison: SyncComparison,
+    pub delete_extras: bool,
+    pub dry_run: bool,
+    pub put_options: PutObjectOptions,
+    pub multipart: MultipartUploadConfig
+}
+
+impl Default for SyncOptions {
+
+    fn default() -> Self {
+        Self {
+            comparison: SyncComparison::SizeAndETag,
+            delete_extras: false,
+            dry_run: false,
+            put_options: PutObjectOptions::default(),
+            multipart: MultipartUploadConfig::default()
+        }
+    }
+}
+
+/// What `sync_dir` did, or would have done on a dry run.
+#[derive(Clone, Debug, Default)]
+pub struct SyncReport {
+    pub uploaded: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub deleted: Vec<String>,
+    pub failed: Vec<SyncFailure>
+}
+
+#[derive(Clone, Debug)]
+pub struct SyncFailure {
+    pub key: String,
+    pub message: String
+}
+
+struct LocalFileHash {
+    size: u64,
+    md5: String,
+    sha256: String
+}
+
+async fn hash_file(path: &Path) -> std::io::Result<LocalFileHash> {
+    let mut file = File::open(path).await?;
+    let mut md5 = Md5::new();
+    let mut sha256 = Sha256::new();
+    let mut size: u64 = 0;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        md5.update(&buf[..read]);
+        sha256.update(&buf[..read]);
+        size += read as u64;
+    }
+
+    Ok(LocalFileHash {
+        size,
+        md5: hex::encode(md5.finalize()),
+        sha256: hex::encode(sha256.finalize())
+    })
+}
+
+/// Every file under `dir`, recursively, in a stable order. Symlinks are
+/// neither followed nor read, so a link can't pull in files from outside
+/// `dir` or loop back on itself; they're returned separately instead.
+async fn list_files(dir: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>), S3Error> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut symlinks: Vec<PathBuf> = Vec::new();
+    let mut dirs: Vec<PathBuf> = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await
+            .map_err(|err| S3Error{message: format!("failed to list {}: {}", dir.display(), err)})?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|err| S3Error{message: format!("failed to list {}: {}", dir.display(), err)})? {
+                let path = entry.path();
+                let file_type = entry
+                    .file_type()
+                    .await
+                    .map_err(|err| S3Error{message: format!("failed to stat {}: {}", path.display(), err)})?;
+                if file_type.is_symlink() {
+                    symlinks.push(path);
+                } else if file_type.is_dir() {
+                    dirs.push(path);
+                } else if file_type.is_file() {
+                    files.push(path);
+                }
+        }
+    }
+    files.sort();
+    symlinks.sort();
+
+    Ok((files, symlinks))
+}
+
+fn local_key(local_dir: &Path, path: &Path, prefix: &str) -> Option<String> {
+    let relative = path.strip_prefix(local_dir).ok()?;
+    let components: Option<Vec<&str>> = relative
+        .components()
+        .map(|component| component.as_os_str().to_str())
+        .collect();
+
+    Some(format!("{}{}", prefix, components?.join("/")))
+}
+
+#[derive(Clone, Debug)]
+pub struct ContentAddressedUpload {
+    pub key: String,
+    pub sha256: String,
+    /// False when an object with the same digest already existed.
+    pub uploaded: bool
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GetObjectConditions {
+    pub if_none_match: Option<String>,
+    pub if_modified_since: Option<DateTime<Utc>>,
+    pub range: Option<ByteRange>
+}
+
+/// An HTTP byte range, inclusive at both ends like the header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteRange {
+    /// `bytes=start-end`
+    Bounded { start: u64, end: u64 },
+    /// `bytes=start-`, everything from `start` on
+    From(u64),
+    /// `bytes=-length`, the last `length` bytes
+    Suffix(u64)
+}
+
+impl ByteRange {
+
+    pub fn to_header(&self) -> String {
+        match self {
+            ByteRange::Bounded { start, end } => format!("bytes={}-{}", start, end),
+            ByteRange::From(start) => format!("bytes={}-", start),
+            ByteRange::Suffix(length) => format!("bytes=-{}", length)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConditionalGetOutput {
+    Modified(Box<GetObjectOutput>),
+    NotModified,
+    NotFound
+}
+
+const DELETE_OBJECTS_MAX_KEYS: usize = 1000;
+
+const MAX_COPY_OBJECT_SIZE: i64 = 5 * 1024 * 1024 * 1024;
+
+const COPY_PART_SIZE: i64 = 512 * 1024 * 1024;
+
+const MIN_UPLOAD_PART_SIZE: usize = 5 * 1024 * 1024;
+
+const MAX_UPLOAD_PART_SIZE: usize = 5 * 1024 * 1024 * 1024;
+
+const MAX_UPLOAD_PARTS: i32 = 10_000;
+
+/// Percent-encodes a key for use in a copy source, leaving `/` intact.
+fn encode_key(key: &str) -> String {
+    key.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b)
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DeleteObjectsReport {
+    pub deleted: Vec<String>,
+    pub errors: Vec<DeleteObjectFailure>
+}
+
+#[derive(Clone, Debug)]
+pub struct DeleteObjectFailure {
+    pub key: String,
+    pub code: Option<String>,
+    pub message: Option<String>
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RelocationReport {
+    /// Old to new key of every object moved.
+    pub moved: HashMap<String, String>,
+    /// Originals left behind after their copy succeeded.
+    pub delete_failures: Vec<DeleteObjectFailure>
+}
+
+/// Headers and user metadata set on uploaded objects. The content type is
+/// detected from the body when not given.
+#[derive(Clone, Debug, Default)]
+pub struct PutObjectOptions {
+    pub content_type: Option<String>,
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub metadata: HashMap<String, String>
+}
+
+impl PutObjectOptions {
+
+    pub(crate) fn content_type_for(&self, key: &str, body: &[u8]) -> String {
+        self.content_type
+            .clone()
+            .unwrap_or_else(|| detect_content_type(key, body))
+    }
+
+    fn user_metadata(&self) -> Option<HashMap<String, String>> {
+        if self.metadata.is_empty() {
+            None
+        } else {
+            Some(self.metadata.clone())
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct S3ObjectMetadata {
+    pub key: String,
+    pub content_type: Option<String>,
+    pub content_length: Option<i64>,
+    pub e_tag: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>,
+    pub cache_control: Option<String>,
+    pub content_disposition: Option<String>,
+    pub content_encoding: Option<String>,
+    pub metadata: HashMap<String, String>
+}
+
+#[derive(Clone, Debug)]
+pub struct MultipartUploadConfig {
+    pub threshold: usize,
+    pub part_size: usize,
+    pub concurrency: usize
+}
+
+impl MultipartUploadConfig {
+
+    /// Checks the part size against the S3 limits of 5 MiB to 5 GiB per part
+    /// and, when the body size is known, that it fits in 10,000 parts.
+    pub fn validate(&self, size: Option<u64>) -> Result<(), S3Error> {
+        if !(MIN_UPLOAD_PART_SIZE..=MAX_UPLOAD_PART_SIZE).contains(&self.part_size) {
+            return Err(S3Error{message: format!(
+                "multipart part size {} is outside the allowed {} to {} bytes",
+                self.part_size, MIN_UPLOAD_PART_SIZE, MAX_UPLOAD_PART_SIZE
+            )});
+        }
+        if let Some(size) = size {
+            let parts = size.div_ceil(self.part_size as u64);
+            if parts > MAX_UPLOAD_PARTS as u64 {
+                return Err(S3Error{message: format!(
+                    "{} bytes need {} parts of {} bytes, more than the allowed {}",
+                    size, parts, self.part_size, MAX_UPLOAD_PARTS
+                )});
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for MultipartUploadConfig {
+
+    fn default() -> Self {
+        Self {
+            threshold: 16 * 1024 * 1024,
+            part_size: 8 * 1024 * 1024,
+            concurrency: 4
+        }
+    }
+}
+
+/// Reads up to `size` bytes, stopping short only at the end of `reader`.
+async fn read_chunk<R>(reader: &mut R, size: usize) -> std::io::Result<Vec<u8>> where R: AsyncRead + Unpin {
+    let mut chunk = Vec::with_capacity(size);
+    reader.take(size as u64).read_to_end(&mut chunk).await?;
+
+    Ok(chunk)
+}
+
+fn flatten_join(joined: Result<Result<CompletedPart, S3Error>, JoinError>) -> Result<CompletedPart, S3Error> {
+    joined.map_err(|err| S3Error{message: format!("part upload task failed: {}", err)})?
+}
+
+/// Progress of a streamed read, passed to `ObjectStreamOptions::progress`.
+#[derive(Clone, Copy, Debug)]
+pub struct TransferProgress {
+    pub transferred: u64,
+    /// The object's content length, when S3 reported one.
+    pub total: Option<u64>
+}
+
+#[derive(Clone, Default)]
+pub struct ObjectStreamOptions {
+    pub max_size: Option<u64>,
+    /// Called after every chunk read from the body.
+    pub progress: Option<Arc<dyn Fn(TransferProgress) + Send + Sync>>
+}
+
+/// An object body read incrementally, from `S3Helper::get_object_reader`.
+pub struct S3ObjectReader {
+    inner: Pin<Box<dyn AsyncBufRead + Send>>,
+    transferred: u64,
+    total: Option<u64>,
+    options: ObjectStreamOptions
+}
+
+impl S3ObjectReader {
+
+    /// The object's content length, when S3 reported one.
+    pub fn total(&self) -> Option<u64> {
+        self.total
+    }
+}
+
+impl AsyncRead for S3ObjectReader {
+
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        ready!(self.inner.as_mut().poll_read(cx, buf))?;
+        let read = (buf.filled().len() - filled) as u64;
+        if read == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        self.transferred += read;
+        if let Some(max_size) = self.options.max_size {
+            if self.transferred > max_size {
+                return Poll::Ready(Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("object is larger than the limit of {} bytes", max_size)
+                )));
+            }
+        }
+        if let Some(progress) = &self.options.progress {
+            progress(TransferProgress { transferred: self.transferred, total: self.total });
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PresignedUploadPolicy {
+    pub allowed_content_types: Vec<String>,
+    pub max_size: i64,
+    pub expires_in: Duration
+}
+
+impl Default for PresignedUploadPolicy {
+
+    fn default() -> Self {
+        Self {
+            allowed_content_types: vec![
+                "image/jpeg".to_string(),
+                "image/png".to_string(),
+                "image/webp".to_string()
+            ],
+            max_size: 20 * 1024 * 1024,
+            expires_in: Duration::from_secs(15 * 60)
+        }
     }
 }
 
+#[derive(Clone, Debug, Serialize)]
+pub struct PresignedUrl {
+    pub url: String,
+    pub method: String,
+    pub headers: HashMap<String, String>,
+    pub expires_at: DateTime<Utc>
+}
+
+impl PresignedUrl {
+
+    fn from_request(request: &PresignedRequest, expires_in: Duration) -> Self {
+        Self {
+            url: request.uri().to_string(),
+            method: request.method().to_string(),
+            headers: request
+                .headers()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            expires_at: Utc::now() + expires_in
+        }
+    }
+}
+
+pub struct S3ObjectPages<'a> {
+    s3_helper: &'a S3Helper,
+    prefix: String,
+    continuation_token: Option<String>,
+    done: bool
+}
+
+impl S3ObjectPages<'_> {
+
+    pub async fn next_page(&mut self) -> Option<Result<Vec<S3ObjectInfo>, S3Error>> {
+        if self.done {
+            return None;
+        }
+
+        let loo: ListObjectsV2Output = match self.s3_helper.s3_client
+            .list_objects_v2()
+            .bucket(&self.s3_helper.bucket)
+            .prefix(&self.prefix)
+            .set_continuation_token(self.continuation_token.take())
+            .send()
+            .await {
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(S3Error{message: format!("failed to list objects under {}: {}", self.prefix, DisplayErrorContext(err))}));
+                },
+                Ok(res) => res
+            };
+
+        self.continuation_token = loo.next_continuation_token().map(|token| token.to_string());
+        self.done = !loo.is_truncated().unwrap_or(false) || self.continuation_token.is_none();
+
+        let objects: &[Object] = loo
+            .contents();
+        Some(Ok(objects
+            .iter()
+            .filter_map(S3ObjectInfo::from_object)
+            .collect()))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct S3ObjectInfo {
+    pub key: String,
+    pub size: Option<i64>,
+    pub e_tag: Option<String>,
+    pub last_modified: Option<DateTime<Utc>>
+}
+
+impl S3ObjectInfo {
+
+    fn from_object(object: &Object) -> Option<Self> {
+        let key = object.key().map_or("", |key| key);
+        if key.is_empty() || key.ends_with('/') {
+            return None;
+        }
+
+        Some(Self {
+            key: key.to_string(),
+            size: object.size(),
+            e_tag: object.e_tag().map(|e_tag| e_tag.to_string()),
+            last_modified: object.last_modified().and_then(to_chrono)
+        })
+    }
+}
+
+fn to_chrono(date_time: &aws_sdk_s3::primitives::DateTime) -> Option<DateTime<Utc>> {
+    DateTime::from_timestamp(date_time.secs(), date_time.subsec_nanos())
+}
+
 #[derive(Debug)]
 pub struct S3Error {
     pub message: String
@@ -125,4 +1488,53 @@ impl std::error::Error for S3Error {
     fn description(&self) -> &str {
         &self.message
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug)]
+pub enum S3JsonError {
+    NotFound { key: String },
+    Transport(S3Error),
+    /// The stored object couldn't be decompressed or deserialized.
+    Parse { key: String, message: String },
+    /// The value passed to `put_json` couldn't be serialized or compressed.
+    Encode { key: String, message: String }
+}
+
+impl fmt::Display for S3JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            S3JsonError::NotFound { key } => write!(f, "object {} does not exist", key),
+            S3JsonError::Transport(err) => write!(f, "{}", err),
+            S3JsonError::Parse { key, message } => write!(f, "invalid json in {}: {}", key, message),
+            S3JsonError::Encode { key, message } => write!(f, "failed to encode json for {}: {}", key, message)
+        }
+    }
+}
+
+impl std::error::Error for S3JsonError {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(part_size: usize) -> MultipartUploadConfig {
+        MultipartUploadConfig {
+            part_size,
+            ..MultipartUploadConfig::default()
+        }
+    }
+
+    #[test]
+    fn multipart_part_size_must_be_within_s3_limits() {
+        assert!(config(5 * 1024 * 1024 - 1).validate(None).is_err());
+        assert!(config(5 * 1024 * 1024 * 1024 + 1).validate(None).is_err());
+        assert!(config(5 * 1024 * 1024).validate(None).is_ok());
+    }
+
+    #[test]
+    fn multipart_known_size_must_fit_in_max_parts() {
+        let part_size = 5 * 1024 * 1024;
+        let max = part_size as u64 * 10_000;
+        assert!(config(part_size).validate(Some(max)).is_ok());
+        assert!(config(part_size).validate(Some(max + 1)).is_err());
+    }
+}
@@ -1,4 +1,8 @@
-use aws_sdk_sqs::{error::SdkError, operation::{delete_message::DeleteMessageError, get_queue_url::GetQueueUrlError, receive_message::ReceiveMessageError, send_message::SendMessageError}, types::Message, Client};
+use core::fmt;
+
+use aws_sdk_sqs::{error::{DisplayErrorContext, SdkError}, operation::{delete_message::DeleteMessageError, get_queue_url::GetQueueUrlError, receive_message::ReceiveMessageError, send_message::SendMessageError}, types::Message, Client};
+
+use crate::model::job::{Job, JobEnvelope, JOB_SCHEMA_VERSION};
 
 #[derive(Clone)]
 pub struct SQSHelper {
@@ -15,7 +19,7 @@ pub async fn create_sqs_helper(aws_config: &aws_config::SdkConfig, queue_name: &
         .send()
         .await?;
 
-    let queue_url = q_url_output.queue_url.expect(&format!("failed to get queue url for queue name: {}", queue_name));
+    let queue_url = q_url_output.queue_url.unwrap_or_else(|| panic!("failed to get queue url for queue name: {}", queue_name));
     Ok(SQSHelper {
         sqs_client,
         queue_url
@@ -55,4 +59,97 @@ impl SQSHelper {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    /// Wraps `job` in a new envelope and sends it, returning the envelope.
+    pub async fn send_job(&self, job: Job) -> Result<JobEnvelope, SQSJobError> {
+        let envelope = JobEnvelope::new(job);
+        self.send_envelope(&envelope).await?;
+
+        Ok(envelope)
+    }
+
+    /// Sends an existing envelope as is, e.g. the result of
+    /// `JobEnvelope::retry`.
+    pub async fn send_envelope(&self, envelope: &JobEnvelope) -> Result<(), SQSJobError> {
+        let body = serde_json::to_string(envelope)
+            .map_err(|err| SQSJobError{message: format!("failed to serialize job {}: {}", envelope.id, err)})?;
+        self.send_message(&body)
+            .await
+            .map_err(|err| SQSJobError{message: format!("failed to send job {}: {}", envelope.id, DisplayErrorContext(err))})
+    }
+
+    /// Receives a batch and parses each message as a `JobEnvelope`.
+    /// Messages that don't parse, or have a schema version newer than this
+    /// build understands, are returned in `malformed` rather than failing the
+    /// batch. They're left on the queue, so delete or dead-letter them.
+    pub async fn receive_jobs(&self) -> Result<ReceivedJobs, SdkError<ReceiveMessageError>> {
+        let mut received = ReceivedJobs::default();
+        for message in self.receive_messages().await? {
+            match parse_job(&message) {
+                Ok((envelope, receipt_handle)) => received.jobs.push(ReceivedJob { envelope, receipt_handle }),
+                Err(reason) => received.malformed.push(MalformedMessage {
+                    message_id: message.message_id,
+                    receipt_handle: message.receipt_handle,
+                    body: message.body,
+                    reason
+                })
+            }
+        }
+
+        Ok(received)
+    }
+}
+
+fn parse_job(message: &Message) -> Result<(JobEnvelope, String), String> {
+    let body = message.body.as_deref().ok_or("message has no body")?;
+    let receipt_handle = message.receipt_handle.clone().ok_or("message has no receipt handle")?;
+    let envelope: JobEnvelope = serde_json::from_str(body).map_err(|err| format!("invalid job envelope: {}", err))?;
+    if envelope.schema_version > JOB_SCHEMA_VERSION {
+        return Err(format!(
+            "job {} has schema version {}, newest supported is {}",
+            envelope.id,
+            envelope.schema_version,
+            JOB_SCHEMA_VERSION
+        ));
+    }
+
+    Ok((envelope, receipt_handle))
+}
+
+#[derive(Debug, Default)]
+pub struct ReceivedJobs {
+    pub jobs: Vec<ReceivedJob>,
+    pub malformed: Vec<MalformedMessage>
+}
+
+#[derive(Debug)]
+pub struct ReceivedJob {
+    pub envelope: JobEnvelope,
+    /// Pass to `delete_message` once the job is done.
+    pub receipt_handle: String
+}
+
+#[derive(Debug)]
+pub struct MalformedMessage {
+    pub message_id: Option<String>,
+    pub receipt_handle: Option<String>,
+    pub body: Option<String>,
+    pub reason: String
+}
+
+#[derive(Debug)]
+pub struct SQSJobError {
+    pub message: String
+}
+
+impl fmt::Display for SQSJobError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for SQSJobError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
@@ -1,6 +1,12 @@
 pub mod avi;
 pub mod geo;
 pub mod hut;
+pub mod job;
 pub mod object;
+pub mod objectkey;
+pub mod query;
+pub mod ranking;
+pub mod savedsearch;
 pub mod search;
-pub mod tripreport;
\ No newline at end of file
+pub mod tripreport;
+pub mod zone;
\ No newline at end of file
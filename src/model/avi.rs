@@ -21,10 +21,10 @@ impl AviReport {
 
     pub fn from_zone_properties(zps: &ZoneProperties) -> Result<Self, chrono::ParseError> {
         let start_time: Option<chrono::NaiveDateTime> = if let Some(start_date) = &zps.start_date {
-            Some(NaiveDateTime::parse_from_str(&start_date, "%Y-%m-%dT%H:%M:%S")?)
+            Some(NaiveDateTime::parse_from_str(start_date, "%Y-%m-%dT%H:%M:%S")?)
         } else { None };
         let end_time: Option<chrono::NaiveDateTime> = if let Some(end_date) = &zps.end_date {
-            Some(NaiveDateTime::parse_from_str(&end_date, "%Y-%m-%dT%H:%M:%S")?)
+            Some(NaiveDateTime::parse_from_str(end_date, "%Y-%m-%dT%H:%M:%S")?)
         } else { None };
 
         Ok(AviReport { 
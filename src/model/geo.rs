@@ -36,3 +36,9 @@ pub struct Properties {
     pub fill: Option<String>,
     pub huttripper_type: Option<String>
 }
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct GeoPoint {
+    pub lat: f64,
+    pub lon: f64
+}
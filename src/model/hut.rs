@@ -17,10 +17,9 @@ impl HutsFilterResult {
 
     pub fn map_from(row: PgRow) -> Result<Self, sqlx::Error> {
         let thumbnail_image: Option<String> = row.try_get("thumbnailimage")?;
-        let image_links = if thumbnail_image.is_none() || thumbnail_image.clone().unwrap().is_empty() {
-            vec![]
-        } else {
-            vec![thumbnail_image.unwrap()]
+        let image_links = match thumbnail_image {
+            Some(thumbnail_image) if !thumbnail_image.is_empty() => vec![thumbnail_image],
+            _ => vec![]
         };
         
         Ok(Self { 
@@ -31,7 +30,7 @@ impl HutsFilterResult {
             system: row.try_get("system")?, 
             max_capacity: row.try_get("maxcapacity")?,
             point: vec![row.try_get("longitude")?, row.try_get("latitude")?],
-            image_links: image_links
+            image_links
         })
     }
 }
\ No newline at end of file
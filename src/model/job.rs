@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::{object::S3PathAttributes, savedsearch::SavedSearchNotification};
+
+/// Bumped when a job's payload changes incompatibly. Consumers reject
+/// envelopes with a newer version instead of misreading them.
+pub const JOB_SCHEMA_VERSION: u32 = 1;
+
+/// Every job sent over SQS, serialized as `"kind"` and `"payload"` fields
+/// of the envelope, e.g. `{"kind": "process_trip_report", "payload":
+/// {"trip_report_id": "..."}}`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "kind", content = "payload", rename_all = "snake_case")]
+pub enum Job {
+    ReindexHut {
+        hut: S3PathAttributes
+    },
+    RegenerateThumbnails {
+        hut: S3PathAttributes
+    },
+    /// Refreshes the named forecast zones, or every zone when empty.
+    RefreshAvalancheForecasts {
+        zone_names: Vec<String>
+    },
+    ProcessTripReport {
+        trip_report_id: String
+    },
+    /// Tells a user that a new trip report matches one of their saved
+    /// searches, from `ESHelper::saved_search_notifications`.
+    NotifySavedSearch(SavedSearchNotification)
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct JobEnvelope {
+    pub id: String,
+    pub schema_version: u32,
+    pub created_at: DateTime<Utc>,
+    /// 1 for the first delivery, incremented by `retry`.
+    pub attempt: u32,
+    #[serde(flatten)]
+    pub job: Job
+}
+
+impl JobEnvelope {
+
+    pub fn new(job: Job) -> Self {
+        Self {
+            id: uuid::Uuid::new_v4().to_string(),
+            schema_version: JOB_SCHEMA_VERSION,
+            created_at: Utc::now(),
+            attempt: 1,
+            job
+        }
+    }
+
+    /// The same job with the same id, for sending again after a failure.
+    pub fn retry(&self) -> Self {
+        Self {
+            attempt: self.attempt + 1,
+            ..self.clone()
+        }
+    }
+}
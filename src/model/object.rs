@@ -1,7 +1,9 @@
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
-use sqlx::{postgres::PgRow, Row};
+use sqlx::{postgres::PgRow, Postgres, QueryBuilder, Row};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct S3PathAttributes {
     pub sanitized_state: String,
     pub sanitized_system: String,
@@ -19,8 +21,48 @@ impl S3PathAttributes {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Links for a hut's `thumbnailimage` and `images` columns. `images` holds
+/// one link per original photo, in display order; thumbnails and resized
+/// copies are derived from those keys and never listed there.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct ImgLinkUpdates {
     pub thumbnail_image: Option<String>,
     pub images: Vec<String>
-}
\ No newline at end of file
+}
+
+impl ImgLinkUpdates {
+
+    /// Rewrites links whose key was moved, e.g. the `moved` keys of
+    /// `S3Helper::relocate_hut_objects`. Links are matched by key suffix, so
+    /// any host or CDN prefix is kept.
+    pub fn relocate(self, relocations: &HashMap<String, String>) -> Self {
+        let relocate_link = |link: String| {
+            relocations
+                .iter()
+                .find(|(old_key, _)| link.ends_with(old_key.as_str()))
+                .map(|(old_key, new_key)| format!("{}{}", &link[..link.len() - old_key.len()], new_key))
+                .unwrap_or(link)
+        };
+
+        Self {
+            thumbnail_image: self.thumbnail_image.map(relocate_link),
+            images: self.images.into_iter().map(relocate_link).collect()
+        }
+    }
+
+    /// `UPDATE` writing these links to the `thumbnailimage` and `images`
+    /// columns of the hut in `table` identified by `attributes`. `table` is
+    /// pushed as-is, so it must not come from user input.
+    pub fn sql_update(&self, table: &str, attributes: &S3PathAttributes) -> QueryBuilder<'static, Postgres> {
+        let mut builder: QueryBuilder<'static, Postgres> = QueryBuilder::new("UPDATE ");
+        builder
+            .push(table)
+            .push(" SET thumbnailimage = ").push_bind(self.thumbnail_image.clone())
+            .push(", images = ").push_bind(self.images.clone())
+            .push(" WHERE sanitizedstate = ").push_bind(attributes.sanitized_state.clone())
+            .push(" AND sanitizedsystem = ").push_bind(attributes.sanitized_system.clone())
+            .push(" AND sanitizedname = ").push_bind(attributes.sanitized_name.clone());
+
+        builder
+    }
+}
@@ -0,0 +1,301 @@
+use core::fmt;
+
+use super::object::S3PathAttributes;
+
+const HUTS: &str = "huts";
+const TRIP_REPORTS: &str = "tripreports";
+const MAPS: &str = "maps";
+const SHA256: &str = "sha256";
+const QUARANTINE: &str = "quarantine";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageVariant {
+    Original,
+    Thumbnail,
+    Responsive
+}
+
+impl ImageVariant {
+
+    pub fn dir(&self) -> &'static str {
+        match self {
+            ImageVariant::Original => "images",
+            ImageVariant::Thumbnail => "thumbnails",
+            ImageVariant::Responsive => "responsive"
+        }
+    }
+
+    fn from_dir(dir: &str) -> Option<Self> {
+        match dir {
+            "images" => Some(ImageVariant::Original),
+            "thumbnails" => Some(ImageVariant::Thumbnail),
+            "responsive" => Some(ImageVariant::Responsive),
+            _ => None
+        }
+    }
+}
+
+/// The canonical S3 key layout shared by every service:
+///
+/// - `huts/{state}/{system}/{name}/{images|thumbnails|responsive}/{file}`
+/// - `tripreports/{id}/{images|thumbnails|responsive}/{file}`
+/// - `maps/{file}`
+/// - `sha256/{hex digest}[.{extension}]`, for content-addressed objects
+/// - `quarantine/tripreports/{id}/{file}`, for trip report uploads waiting
+///   for approval
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObjectKey {
+    HutImage {
+        attributes: S3PathAttributes,
+        variant: ImageVariant,
+        file_name: String
+    },
+    TripReportImage {
+        trip_report_id: String,
+        variant: ImageVariant,
+        file_name: String
+    },
+    MapData {
+        file_name: String
+    },
+    ContentAddressed {
+        sha256: String,
+        extension: Option<String>
+    },
+    QuarantinedTripReportImage {
+        trip_report_id: String,
+        file_name: String
+    }
+}
+
+impl ObjectKey {
+
+    pub fn to_key(&self) -> Result<String, ObjectKeyError> {
+        match self {
+            ObjectKey::HutImage { attributes, variant, file_name } => Ok(format!(
+                "{}{}/{}",
+                hut_prefix(attributes)?,
+                variant.dir(),
+                safe_component(file_name)?
+            )),
+            ObjectKey::TripReportImage { trip_report_id, variant, file_name } => Ok(format!(
+                "{}{}/{}",
+                trip_report_prefix(trip_report_id)?,
+                variant.dir(),
+                safe_component(file_name)?
+            )),
+            ObjectKey::MapData { file_name } => Ok(format!("{}/{}", MAPS, safe_component(file_name)?)),
+            ObjectKey::ContentAddressed { sha256, extension } => {
+                let sha256 = valid_sha256(sha256)?;
+                match extension {
+                    Some(extension) => Ok(format!("{}/{}.{}", SHA256, sha256, safe_component(extension)?)),
+                    None => Ok(format!("{}/{}", SHA256, sha256))
+                }
+            },
+            ObjectKey::QuarantinedTripReportImage { trip_report_id, file_name } => Ok(format!(
+                "{}{}",
+                quarantined_trip_report_prefix(trip_report_id)?,
+                safe_component(file_name)?
+            ))
+        }
+    }
+
+    pub fn parse(key: &str) -> Result<Self, ObjectKeyError> {
+        let components: Vec<&str> = key.split('/').collect();
+        for component in &components {
+            safe_component(component)?;
+        }
+
+        match components.as_slice() {
+            [HUTS, state, system, name, dir, file_name] => Ok(ObjectKey::HutImage {
+                attributes: S3PathAttributes {
+                    sanitized_state: state.to_string(),
+                    sanitized_system: system.to_string(),
+                    sanitized_name: name.to_string()
+                },
+                variant: parse_variant(key, dir)?,
+                file_name: file_name.to_string()
+            }),
+            [TRIP_REPORTS, trip_report_id, dir, file_name] => Ok(ObjectKey::TripReportImage {
+                trip_report_id: trip_report_id.to_string(),
+                variant: parse_variant(key, dir)?,
+                file_name: file_name.to_string()
+            }),
+            [MAPS, file_name] => Ok(ObjectKey::MapData {
+                file_name: file_name.to_string()
+            }),
+            [SHA256, file_name] => {
+                let (sha256, extension) = match file_name.split_once('.') {
+                    Some((sha256, extension)) => (sha256, Some(extension.to_string())),
+                    None => (*file_name, None)
+                };
+                Ok(ObjectKey::ContentAddressed {
+                    sha256: valid_sha256(sha256)?.to_string(),
+                    extension
+                })
+            },
+            [QUARANTINE, TRIP_REPORTS, trip_report_id, file_name] => Ok(ObjectKey::QuarantinedTripReportImage {
+                trip_report_id: trip_report_id.to_string(),
+                file_name: file_name.to_string()
+            }),
+            _ => Err(ObjectKeyError{message: format!("{} does not match any known key layout", key)})
+        }
+    }
+
+    /// The same hut or trip report image as a different variant, e.g. the
+    /// thumbnail of an original. `None` for other kinds of keys.
+    pub fn with_variant(&self, variant: ImageVariant, file_name: &str) -> Option<Self> {
+        match self {
+            ObjectKey::HutImage { attributes, .. } => Some(ObjectKey::HutImage {
+                attributes: attributes.clone(),
+                variant,
+                file_name: file_name.to_string()
+            }),
+            ObjectKey::TripReportImage { trip_report_id, .. } => Some(ObjectKey::TripReportImage {
+                trip_report_id: trip_report_id.clone(),
+                variant,
+                file_name: file_name.to_string()
+            }),
+            ObjectKey::MapData { .. }
+            | ObjectKey::ContentAddressed { .. }
+            | ObjectKey::QuarantinedTripReportImage { .. } => None
+        }
+    }
+}
+
+/// Prefix of every object belonging to a hut, ending in `/`.
+pub fn hut_prefix(attributes: &S3PathAttributes) -> Result<String, ObjectKeyError> {
+    Ok(format!(
+        "{}/{}/{}/{}/",
+        HUTS,
+        safe_component(&attributes.sanitized_state)?,
+        safe_component(&attributes.sanitized_system)?,
+        safe_component(&attributes.sanitized_name)?
+    ))
+}
+
+/// Prefix of every object belonging to a trip report, ending in `/`.
+pub fn trip_report_prefix(trip_report_id: &str) -> Result<String, ObjectKeyError> {
+    Ok(format!("{}/{}/", TRIP_REPORTS, safe_component(trip_report_id)?))
+}
+
+/// Prefix of every quarantined trip report upload, ending in `/`.
+pub fn quarantined_trip_reports_prefix() -> String {
+    format!("{}/{}/", QUARANTINE, TRIP_REPORTS)
+}
+
+/// Prefix of the quarantined uploads of one trip report, ending in `/`.
+pub fn quarantined_trip_report_prefix(trip_report_id: &str) -> Result<String, ObjectKeyError> {
+    Ok(format!("{}{}/", quarantined_trip_reports_prefix(), safe_component(trip_report_id)?))
+}
+
+/// Rejects anything that could escape or alter the layout: empty and dot
+/// components, separators, and characters outside `[A-Za-z0-9._-]`.
+pub fn safe_component(component: &str) -> Result<&str, ObjectKeyError> {
+    let valid = !component.is_empty()
+        && !component.starts_with('.')
+        && component.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if valid {
+        Ok(component)
+    } else {
+        Err(ObjectKeyError{message: format!("unsafe key component: {:?}", component)})
+    }
+}
+
+fn valid_sha256(sha256: &str) -> Result<&str, ObjectKeyError> {
+    if sha256.len() == 64 && sha256.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')) {
+        Ok(sha256)
+    } else {
+        Err(ObjectKeyError{message: format!("invalid sha256 digest: {:?}", sha256)})
+    }
+}
+
+fn parse_variant(key: &str, dir: &str) -> Result<ImageVariant, ObjectKeyError> {
+    ImageVariant::from_dir(dir)
+        .ok_or_else(|| ObjectKeyError{message: format!("{} has unknown image directory {}", key, dir)})
+}
+
+#[derive(Debug)]
+pub struct ObjectKeyError {
+    pub message: String
+}
+
+impl fmt::Display for ObjectKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ObjectKeyError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(key: ObjectKey, expected: &str) {
+        assert_eq!(key.to_key().unwrap(), expected);
+        assert_eq!(ObjectKey::parse(expected).unwrap(), key);
+    }
+
+    #[test]
+    fn hut_and_trip_report_images_round_trip() {
+        round_trip(ObjectKey::HutImage {
+            attributes: S3PathAttributes {
+                sanitized_state: "colorado".to_string(),
+                sanitized_system: "10th-mountain".to_string(),
+                sanitized_name: "janets-cabin".to_string()
+            },
+            variant: ImageVariant::Thumbnail,
+            file_name: "front.jpg".to_string()
+        }, "huts/colorado/10th-mountain/janets-cabin/thumbnails/front.jpg");
+        round_trip(ObjectKey::TripReportImage {
+            trip_report_id: "abc123".to_string(),
+            variant: ImageVariant::Original,
+            file_name: "summit.png".to_string()
+        }, "tripreports/abc123/images/summit.png");
+    }
+
+    #[test]
+    fn map_content_addressed_and_quarantine_keys_round_trip() {
+        let sha256 = "a".repeat(64);
+        round_trip(ObjectKey::MapData {
+            file_name: "zones.geojson".to_string()
+        }, "maps/zones.geojson");
+        round_trip(ObjectKey::ContentAddressed {
+            sha256: sha256.clone(),
+            extension: Some("jpg".to_string())
+        }, &format!("sha256/{}.jpg", sha256));
+        round_trip(ObjectKey::ContentAddressed {
+            sha256: sha256.clone(),
+            extension: None
+        }, &format!("sha256/{}", sha256));
+        round_trip(ObjectKey::QuarantinedTripReportImage {
+            trip_report_id: "abc123".to_string(),
+            file_name: "upload.jpg".to_string()
+        }, "quarantine/tripreports/abc123/upload.jpg");
+    }
+
+    #[test]
+    fn rejects_unknown_layouts() {
+        assert!(ObjectKey::parse("tripreports/abc123/originals/summit.png").is_err());
+        assert!(ObjectKey::parse("quarantine/huts/abc123/upload.jpg").is_err());
+        assert!(ObjectKey::parse("sha256/not-a-digest").is_err());
+        assert!(ObjectKey::parse("tripreports/abc123/images/nested/summit.png").is_err());
+    }
+
+    #[test]
+    fn safe_component_rejects_escapes() {
+        for component in ["", ".", "..", ".hidden", "a/b", "a\\b", "caf\u{e9}", "a b"] {
+            assert!(safe_component(component).is_err(), "{:?} should be rejected", component);
+        }
+        assert!(ObjectKey::parse("tripreports/../images/summit.png").is_err());
+        assert!(ObjectKey::parse("maps//zones.geojson").is_err());
+        assert!(ObjectKey::MapData { file_name: "../zones.geojson".to_string() }.to_key().is_err());
+        assert!(quarantined_trip_report_prefix("a/b").is_err());
+        assert_eq!(safe_component("summit-1_v2.jpg").unwrap(), "summit-1_v2.jpg");
+    }
+}
@@ -0,0 +1,420 @@
+use core::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{Postgres, QueryBuilder};
+
+/// Structured form of the advanced hut search syntax, e.g.
+/// `state:colorado capacity>=8 sauna "10th mountain"`.
+///
+/// Supported fields are `state`, `system`, `capacity` (or `max_capacity`)
+/// and `amenity` (or `amenities`). Anything else is free text, with double
+/// quotes grouping a phrase.
+///
+/// Field filters match whole values, ignoring case, in both the
+/// Elasticsearch and SQL output: `system:"10th Mountain Division Huts"`
+/// matches, `system:10th` doesn't. Free text matches partially.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct HutQuery {
+    pub states: Vec<String>,
+    pub systems: Vec<String>,
+    pub capacity: Vec<CapacityFilter>,
+    pub amenities: Vec<String>,
+    pub text: Vec<TextTerm>
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum TextTerm {
+    Word(String),
+    Phrase(String)
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub struct CapacityFilter {
+    pub comparison: Comparison,
+    pub value: i32
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, PartialEq)]
+pub enum Comparison {
+    Eq,
+    Gt,
+    Gte,
+    Lt,
+    Lte
+}
+
+impl HutQuery {
+
+    pub fn parse(input: &str) -> Result<Self, HutQueryParseError> {
+        let mut query = HutQuery::default();
+        let mut pos = 0;
+
+        while pos < input.len() {
+            let rest = &input[pos..];
+            let trimmed = rest.trim_start();
+            pos += rest.len() - trimmed.len();
+            if trimmed.is_empty() {
+                break;
+            }
+
+            if trimmed.starts_with('"') {
+                let (phrase, consumed) = read_quoted(input, pos)?;
+                if !phrase.is_empty() {
+                    query.text.push(TextTerm::Phrase(phrase));
+                }
+                pos += consumed;
+                continue;
+            }
+
+            let token_len = trimmed
+                .find(|c: char| c.is_whitespace() || c == '"')
+                .unwrap_or(trimmed.len());
+            let token = &trimmed[..token_len];
+            let start = pos;
+            pos += token_len;
+
+            let Some(op_idx) = token.find([':', '<', '>', '=']) else {
+                query.text.push(TextTerm::Word(token.to_string()));
+                continue;
+            };
+            let field = &token[..op_idx];
+            if field.is_empty() || !field.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+                query.text.push(TextTerm::Word(token.to_string()));
+                continue;
+            }
+
+            let after_field = &token[op_idx..];
+            let op_len = after_field
+                .find(|c: char| !matches!(c, ':' | '<' | '>' | '='))
+                .unwrap_or(after_field.len());
+            let op = &after_field[..op_len];
+            let mut value = after_field[op_len..].to_string();
+            if value.is_empty() && input[pos..].starts_with('"') {
+                let (phrase, consumed) = read_quoted(input, pos)?;
+                value = phrase;
+                pos += consumed;
+            }
+            if value.is_empty() {
+                return Err(HutQueryParseError::new(start, format!("missing value for field '{}'", field)));
+            }
+
+            match field.to_lowercase().as_str() {
+                "state" => query.states.push(expect_equality(field, op, start, value)?),
+                "system" => query.systems.push(expect_equality(field, op, start, value)?),
+                "amenity" | "amenities" => query.amenities.push(expect_equality(field, op, start, value)?),
+                "capacity" | "max_capacity" => {
+                    let comparison = match op {
+                        ":" | "=" => Comparison::Eq,
+                        ">" => Comparison::Gt,
+                        ">=" => Comparison::Gte,
+                        "<" => Comparison::Lt,
+                        "<=" => Comparison::Lte,
+                        _ => return Err(HutQueryParseError::new(start, format!("unsupported operator '{}' for field '{}'", op, field)))
+                    };
+                    let value: i32 = value
+                        .parse()
+                        .ok()
+                        .filter(|v: &i32| *v >= 0)
+                        .ok_or_else(|| HutQueryParseError::new(start, format!("'{}' is not a valid capacity", value)))?;
+                    query.capacity.push(CapacityFilter { comparison, value });
+                },
+                _ => return Err(HutQueryParseError::new(start, format!("unknown field '{}'", field)))
+            }
+        }
+
+        Ok(query)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+            && self.systems.is_empty()
+            && self.capacity.is_empty()
+            && self.amenities.is_empty()
+            && self.text.is_empty()
+    }
+
+    /// Builds an Elasticsearch query clause against the hut search index,
+    /// matching free text against `text_fields`, normally
+    /// `HutRankingConfig::text_fields`. Field filters don't affect scoring;
+    /// free text does.
+    pub fn to_es_query(&self, text_fields: &[String]) -> Value {
+        let must: Vec<Value> = if self.text.is_empty() {
+            vec![json!({ "match_all": {} })]
+        } else {
+            self.text.iter().map(|term| match term {
+                TextTerm::Word(word) => json!({
+                    "multi_match": { "query": word, "fields": text_fields, "fuzziness": "AUTO" }
+                }),
+                TextTerm::Phrase(phrase) => json!({
+                    "multi_match": { "query": phrase, "fields": text_fields, "type": "phrase" }
+                })
+            }).collect()
+        };
+
+        let mut filter: Vec<Value> = Vec::new();
+        // the keyword fields are lowercased, like lower() in the SQL filter
+        for state in &self.states {
+            filter.push(json!({ "term": { "state.keyword": state } }));
+        }
+        for system in &self.systems {
+            filter.push(json!({ "term": { "system.keyword": system } }));
+        }
+        for amenity in &self.amenities {
+            filter.push(json!({ "term": { "amenity_tags": amenity } }));
+        }
+        for capacity in &self.capacity {
+            filter.push(match capacity.comparison {
+                Comparison::Eq => json!({ "term": { "max_capacity": capacity.value } }),
+                comparison => json!({ "range": { "max_capacity": { comparison.es_operator(): capacity.value } } })
+            });
+        }
+
+        json!({
+            "bool": {
+                "must": must,
+                "filter": filter
+            }
+        })
+    }
+
+    /// Pushes a boolean SQL expression matching this query onto `builder`,
+    /// against the columns `HutsFilterResult::map_from` reads. Pushes `TRUE`
+    /// when the query has no terms.
+    pub fn push_sql_filter(&self, builder: &mut QueryBuilder<'_, Postgres>) {
+        let mut first = true;
+        let mut and = |builder: &mut QueryBuilder<'_, Postgres>| {
+            if !first {
+                builder.push(" AND ");
+            }
+            first = false;
+        };
+
+        for state in &self.states {
+            and(builder);
+            builder.push("lower(state) = lower(").push_bind(state.clone()).push(")");
+        }
+        for system in &self.systems {
+            and(builder);
+            builder.push("lower(system) = lower(").push_bind(system.clone()).push(")");
+        }
+        for amenity in &self.amenities {
+            and(builder);
+            builder
+                .push("EXISTS (SELECT 1 FROM unnest(amenities) AS amenity WHERE lower(amenity) = lower(")
+                .push_bind(amenity.clone())
+                .push("))");
+        }
+        for capacity in &self.capacity {
+            and(builder);
+            builder
+                .push("maxcapacity ")
+                .push(capacity.comparison.sql_operator())
+                .push(" ")
+                .push_bind(capacity.value);
+        }
+        for term in &self.text {
+            let pattern = match term {
+                TextTerm::Word(text) | TextTerm::Phrase(text) => format!("%{}%", escape_like(text))
+            };
+            and(builder);
+            builder
+                .push("(name ILIKE ").push_bind(pattern.clone())
+                .push(" OR system ILIKE ").push_bind(pattern.clone())
+                .push(" OR state ILIKE ").push_bind(pattern.clone())
+                .push(" OR array_to_string(amenities, ' ') ILIKE ").push_bind(pattern)
+                .push(")");
+        }
+
+        if first {
+            builder.push("TRUE");
+        }
+    }
+}
+
+impl Comparison {
+
+    fn es_operator(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "eq",
+            Comparison::Gt => "gt",
+            Comparison::Gte => "gte",
+            Comparison::Lt => "lt",
+            Comparison::Lte => "lte"
+        }
+    }
+
+    fn sql_operator(&self) -> &'static str {
+        match self {
+            Comparison::Eq => "=",
+            Comparison::Gt => ">",
+            Comparison::Gte => ">=",
+            Comparison::Lt => "<",
+            Comparison::Lte => "<="
+        }
+    }
+}
+
+/// Reads a double quoted string starting at `start`, returning its contents
+/// and the number of bytes consumed including both quotes.
+fn read_quoted(input: &str, start: usize) -> Result<(String, usize), HutQueryParseError> {
+    let body = &input[start + 1..];
+    match body.find('"') {
+        Some(end) => Ok((body[..end].trim().to_string(), end + 2)),
+        None => Err(HutQueryParseError::new(start, "unterminated quote".to_string()))
+    }
+}
+
+fn expect_equality(field: &str, op: &str, position: usize, value: String) -> Result<String, HutQueryParseError> {
+    match op {
+        ":" | "=" => Ok(value),
+        _ => Err(HutQueryParseError::new(position, format!("unsupported operator '{}' for field '{}'", op, field)))
+    }
+}
+
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+#[derive(Debug)]
+pub struct HutQueryParseError {
+    pub message: String,
+    pub position: usize
+}
+
+impl HutQueryParseError {
+
+    fn new(position: usize, message: String) -> Self {
+        Self { message, position }
+    }
+}
+
+impl fmt::Display for HutQueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl std::error::Error for HutQueryParseError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fields_and_free_text() {
+        let query = HutQuery::parse("state:colorado system=10th sauna amenity:wood_stove").unwrap();
+        assert_eq!(query.states, vec!["colorado".to_string()]);
+        assert_eq!(query.systems, vec!["10th".to_string()]);
+        assert_eq!(query.amenities, vec!["wood_stove".to_string()]);
+        assert_eq!(query.text, vec![TextTerm::Word("sauna".to_string())]);
+    }
+
+    #[test]
+    fn parses_quoted_values_and_phrases() {
+        let query = HutQuery::parse(r#"system:"10th mountain" "janet's cabin" hut"#).unwrap();
+        assert_eq!(query.systems, vec!["10th mountain".to_string()]);
+        assert_eq!(query.text, vec![
+            TextTerm::Phrase("janet's cabin".to_string()),
+            TextTerm::Word("hut".to_string())
+        ]);
+    }
+
+    #[test]
+    fn parses_comparison_operators() {
+        let query = HutQuery::parse("capacity:4 capacity>5 capacity>=6 max_capacity<7 capacity<=8").unwrap();
+        let comparisons: Vec<(Comparison, i32)> = query.capacity
+            .iter()
+            .map(|capacity| (capacity.comparison, capacity.value))
+            .collect();
+        assert_eq!(comparisons, vec![
+            (Comparison::Eq, 4),
+            (Comparison::Gt, 5),
+            (Comparison::Gte, 6),
+            (Comparison::Lt, 7),
+            (Comparison::Lte, 8)
+        ]);
+    }
+
+    #[test]
+    fn rejects_bad_operators_and_values() {
+        assert!(HutQuery::parse("state>colorado").is_err());
+        assert!(HutQuery::parse("capacity>=lots").is_err());
+        assert!(HutQuery::parse("capacity:-1").is_err());
+        assert_eq!(HutQuery::parse("sauna state:").unwrap_err().position, 6);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let err = HutQuery::parse("hut elevation>3000").unwrap_err();
+        assert_eq!(err.position, 4);
+        assert!(err.message.contains("elevation"));
+    }
+
+    #[test]
+    fn treats_non_field_tokens_as_text() {
+        let query = HutQuery::parse("10:30 :colon").unwrap();
+        assert_eq!(query.text, vec![
+            TextTerm::Word("10:30".to_string()),
+            TextTerm::Word(":colon".to_string())
+        ]);
+    }
+
+    #[test]
+    fn rejects_unterminated_quotes() {
+        assert_eq!(HutQuery::parse(r#"sauna "janet's"#).unwrap_err().position, 6);
+        assert_eq!(HutQuery::parse(r#"system:"10th"#).unwrap_err().position, 7);
+    }
+
+    #[test]
+    fn handles_non_ascii_input() {
+        let query = HutQuery::parse("hütte state:québec \u{a0}\"cabane à sucre\" 山小屋 état:x").unwrap();
+        assert_eq!(query.states, vec!["québec".to_string()]);
+        assert_eq!(query.text, vec![
+            TextTerm::Word("hütte".to_string()),
+            TextTerm::Phrase("cabane à sucre".to_string()),
+            TextTerm::Word("山小屋".to_string()),
+            TextTerm::Word("état:x".to_string())
+        ]);
+
+        // positions are byte offsets
+        assert_eq!(HutQuery::parse("ü capacity>=２").unwrap_err().position, 3);
+        assert_eq!(HutQuery::parse(r#"ü "à"#).unwrap_err().position, 3);
+    }
+
+    #[test]
+    fn es_and_sql_filters_agree() {
+        let query = HutQuery::parse("state:Colorado system=\"10th Mountain\" amenity:sauna capacity>=8 janet").unwrap();
+
+        // every field filter is an exact, case-insensitive match on both
+        // sides, against the same field in the same order
+        let es = query.to_es_query(&["name".to_string()]);
+        assert_eq!(es["bool"]["filter"], json!([
+            { "term": { "state.keyword": "Colorado" } },
+            { "term": { "system.keyword": "10th Mountain" } },
+            { "term": { "amenity_tags": "sauna" } },
+            { "range": { "max_capacity": { "gte": 8 } } }
+        ]));
+
+        let mut builder: QueryBuilder<'_, Postgres> = QueryBuilder::new("");
+        query.push_sql_filter(&mut builder);
+        assert_eq!(builder.sql(), [
+            "lower(state) = lower($1)",
+            "lower(system) = lower($2)",
+            "EXISTS (SELECT 1 FROM unnest(amenities) AS amenity WHERE lower(amenity) = lower($3))",
+            "maxcapacity >= $4",
+            "(name ILIKE $5 OR system ILIKE $6 OR state ILIKE $7 OR array_to_string(amenities, ' ') ILIKE $8)"
+        ].join(" AND "));
+    }
+
+    #[test]
+    fn uses_the_given_text_fields() {
+        let fields = vec!["name^5".to_string()];
+        let query = HutQuery::parse("janet").unwrap().to_es_query(&fields);
+        assert_eq!(query["bool"]["must"][0]["multi_match"]["fields"], json!(["name^5"]));
+    }
+}
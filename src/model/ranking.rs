@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::geo::GeoPoint;
+
+/// Ranking configuration for hut search. The text relevance score is
+/// multiplied by the sum of the popularity and proximity functions, so a
+/// hut with a strong text match but no signals still keeps its base score.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HutRankingConfig {
+    pub text_fields: Vec<String>,
+    pub base_weight: f64,
+    pub trip_report_count: FieldValueFactor,
+    pub max_capacity: FieldValueFactor,
+    pub recency: DecayFunction,
+    pub distance: DecayFunction
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct FieldValueFactor {
+    pub weight: f64,
+    pub factor: f64,
+    pub modifier: String
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DecayFunction {
+    pub weight: f64,
+    pub scale: String,
+    pub offset: String,
+    pub decay: f64
+}
+
+impl Default for HutRankingConfig {
+
+    fn default() -> Self {
+        Self {
+            text_fields: vec![
+                "name^3".to_string(),
+                "system^2".to_string(),
+                "state".to_string(),
+                "amenities".to_string()
+            ],
+            base_weight: 1.0,
+            trip_report_count: FieldValueFactor {
+                weight: 1.0,
+                factor: 1.0,
+                modifier: "log1p".to_string()
+            },
+            max_capacity: FieldValueFactor {
+                weight: 0.5,
+                factor: 0.1,
+                modifier: "log1p".to_string()
+            },
+            recency: DecayFunction {
+                weight: 1.0,
+                scale: "90d".to_string(),
+                offset: "14d".to_string(),
+                decay: 0.5
+            },
+            distance: DecayFunction {
+                weight: 2.0,
+                scale: "50km".to_string(),
+                offset: "5km".to_string(),
+                decay: 0.5
+            }
+        }
+    }
+}
+
+impl HutRankingConfig {
+
+    /// Builds a search body for `text`. The distance decay is only applied
+    /// when the caller knows where the user is.
+    pub fn to_query(&self, text: &str, origin: Option<&GeoPoint>) -> Value {
+        self.rank_query(json!({
+            "multi_match": {
+                "query": text,
+                "fields": self.text_fields,
+                "fuzziness": "AUTO"
+            }
+        }), origin)
+    }
+
+    /// Wraps an arbitrary query clause in the ranking functions, e.g. one
+    /// produced by `HutQuery::to_es_query` with this config's `text_fields`.
+    pub fn rank_query(&self, query: Value, origin: Option<&GeoPoint>) -> Value {
+        let mut functions = vec![
+            json!({ "weight": self.base_weight }),
+            self.trip_report_count.to_function("trip_report_count"),
+            self.max_capacity.to_function("max_capacity"),
+            json!({
+                "filter": { "exists": { "field": "latest_trip_report" } },
+                "gauss": {
+                    "latest_trip_report": {
+                        "origin": "now",
+                        "scale": self.recency.scale,
+                        "offset": self.recency.offset,
+                        "decay": self.recency.decay
+                    }
+                },
+                "weight": self.recency.weight
+            })
+        ];
+        if let Some(origin) = origin {
+            functions.push(json!({
+                "gauss": {
+                    "point": {
+                        "origin": origin,
+                        "scale": self.distance.scale,
+                        "offset": self.distance.offset,
+                        "decay": self.distance.decay
+                    }
+                },
+                "weight": self.distance.weight
+            }));
+        }
+
+        json!({
+            "query": {
+                "function_score": {
+                    "query": query,
+                    "functions": functions,
+                    "score_mode": "sum",
+                    "boost_mode": "multiply"
+                }
+            }
+        })
+    }
+}
+
+impl FieldValueFactor {
+
+    fn to_function(&self, field: &str) -> Value {
+        json!({
+            "field_value_factor": {
+                "field": field,
+                "factor": self.factor,
+                "modifier": self.modifier,
+                "missing": 0
+            },
+            "weight": self.weight
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{postgres::PgRow, Row};
+
+const PERCOLATED_FIELDS: [&str; 3] = ["hut_conditions", "weather_conditions", "riding_conditions"];
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SavedSearch {
+    pub id: String,
+    pub user_id: String,
+    pub query: String
+}
+
+impl SavedSearch {
+
+    pub fn map_from(row: PgRow) -> Result<Self, sqlx::Error> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            user_id: row.try_get("userid")?,
+            query: row.try_get("query")?
+        })
+    }
+
+    /// Document stored in the saved search index. The user's query is
+    /// matched against the text of each approved trip report, and supports
+    /// quoted phrases, `+`/`-` and `|`.
+    pub fn to_percolator_document(&self) -> Value {
+        json!({
+            "saved_search_id": self.id,
+            "user_id": self.user_id,
+            "query": {
+                "simple_query_string": {
+                    "query": self.query,
+                    "fields": PERCOLATED_FIELDS,
+                    "default_operator": "and"
+                }
+            }
+        })
+    }
+
+    /// Index body for the saved search index. Besides the percolator field,
+    /// it has to map every field the stored queries reference.
+    pub fn index_body() -> Value {
+        json!({
+            "mappings": {
+                "properties": {
+                    "saved_search_id": { "type": "keyword" },
+                    "user_id": { "type": "keyword" },
+                    "query": { "type": "percolator" },
+                    "id": { "type": "keyword" },
+                    "hut_conditions": { "type": "text" },
+                    "weather_conditions": { "type": "text" },
+                    "riding_conditions": { "type": "text" }
+                }
+            }
+        })
+    }
+}
+
+/// One saved search matched by a trip report. Queue it for delivery as
+/// `Job::NotifySavedSearch`.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SavedSearchNotification {
+    pub saved_search_id: String,
+    pub user_id: String,
+    pub trip_report_id: String
+}
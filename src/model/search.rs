@@ -1,27 +1,130 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use sqlx::postgres::PgRow;
 use sqlx::Row;
 
+use super::tripreport::TripReport;
+
+/// `None` when `column` isn't in the row, other errors pass through.
+fn try_get_optional<'r, T>(row: &'r PgRow, column: &str) -> Result<Option<T>, sqlx::Error>
+where
+    T: sqlx::Decode<'r, sqlx::Postgres> + sqlx::Type<sqlx::Postgres>
+{
+    match row.try_get(column) {
+        Ok(value) => Ok(Some(value)),
+        Err(sqlx::Error::ColumnNotFound(_)) => Ok(None),
+        Err(err) => Err(err)
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct HutSearchRepresentation {
     pub name: String,
     pub sanitized_name: String,
     pub system: String,
     pub state: String,
-    pub amenities: String
+    pub amenities: String,
+    /// The amenities one per value, for exact `amenity:` filters.
+    pub amenity_tags: Vec<String>,
+    /// Ranking signals, left out of the document rather than indexed as 0
+    /// when the row doesn't have them, so capacity filters and
+    /// `FieldValueFactor::missing` see them as missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_capacity: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip_report_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latest_trip_report: Option<chrono::NaiveDate>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub point: Vec<f64>
 }
 
 impl HutSearchRepresentation {
 
+    /// The ranking columns (`maxcapacity`, `tripreportcount`,
+    /// `latesttripreport`, `longitude` and `latitude`) are left empty when
+    /// the query doesn't select them, so indexer queries written before they
+    /// existed keep working.
     pub fn map_from(row: PgRow) -> Result<Self, sqlx::Error> {
         let amenities: Vec<String> = row.try_get("amenities")?;
-        amenities.join(" ");
+        let longitude: Option<f64> = try_get_optional(&row, "longitude")?;
+        let latitude: Option<f64> = try_get_optional(&row, "latitude")?;
         Ok(Self {
             name: row.try_get("name")?,
             sanitized_name: row.try_get("sanitizedname")?,
             system: row.try_get("system")?,
             state: row.try_get("state")?,
-            amenities: amenities.join(" ")
+            amenities: amenities.join(" "),
+            amenity_tags: amenities,
+            max_capacity: try_get_optional(&row, "maxcapacity")?.flatten(),
+            trip_report_count: try_get_optional(&row, "tripreportcount")?.flatten(),
+            latest_trip_report: try_get_optional(&row, "latesttripreport")?.flatten(),
+            point: match (longitude, latitude) {
+                (Some(longitude), Some(latitude)) => vec![longitude, latitude],
+                _ => vec![]
+            }
+        })
+    }
+
+    /// Index settings and mappings for the hut search index. The ranking
+    /// signals need explicit types so function_score can read them, and
+    /// `name.trigram` backs the phrase suggester. The lowercased keyword
+    /// fields back the exact field filters of `HutQuery`.
+    pub fn index_body() -> Value {
+        json!({
+            "settings": {
+                "analysis": {
+                    "analyzer": {
+                        "trigram": {
+                            "type": "custom",
+                            "tokenizer": "standard",
+                            "filter": ["lowercase", "shingle"]
+                        }
+                    },
+                    "normalizer": {
+                        "lowercase": {
+                            "type": "custom",
+                            "filter": ["lowercase"]
+                        }
+                    },
+                    "filter": {
+                        "shingle": {
+                            "type": "shingle",
+                            "min_shingle_size": 2,
+                            "max_shingle_size": 3
+                        }
+                    }
+                }
+            },
+            "mappings": {
+                "properties": {
+                    "name": {
+                        "type": "text",
+                        "fields": {
+                            "trigram": { "type": "text", "analyzer": "trigram" }
+                        }
+                    },
+                    "sanitized_name": { "type": "keyword" },
+                    "system": {
+                        "type": "text",
+                        "fields": {
+                            "keyword": { "type": "keyword", "normalizer": "lowercase" }
+                        }
+                    },
+                    "state": {
+                        "type": "text",
+                        "fields": {
+                            "keyword": { "type": "keyword", "normalizer": "lowercase" }
+                        }
+                    },
+                    "amenities": { "type": "text" },
+                    "amenity_tags": { "type": "keyword", "normalizer": "lowercase" },
+                    "max_capacity": { "type": "integer" },
+                    "trip_report_count": { "type": "long" },
+                    "latest_trip_report": { "type": "date", "format": "yyyy-MM-dd" },
+                    "point": { "type": "geo_point" }
+                }
+            }
         })
     }
 }
@@ -36,6 +139,15 @@ pub struct TripReportSearchRepresentation {
 
 impl TripReportSearchRepresentation {
 
+    pub fn from_trip_report(trip_report: &TripReport) -> Self {
+        Self {
+            id: trip_report.id.clone(),
+            hut_conditions: trip_report.hut_conditions.clone(),
+            weather_conditions: trip_report.weather_conditions.clone().unwrap_or_default(),
+            riding_conditions: trip_report.riding_conditions.clone().unwrap_or_default()
+        }
+    }
+
     pub fn map_from(row: PgRow) -> Result<Self, sqlx::Error> {
         Ok(Self {
             id: row.try_get("id")?,
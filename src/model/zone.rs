@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::geo::{Feature, GeoPoint};
+
+/// A forecast zone geometry as stored in the zone shape index.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ZoneShapeDocument {
+    pub zone_id: String,
+    pub zone_name: String,
+    pub geometry: Value
+}
+
+impl ZoneShapeDocument {
+
+    /// Zones without a feature id are keyed by their title so they can
+    /// still be referenced from an `indexed_shape` query.
+    pub fn from_feature(feature: &Feature) -> Self {
+        Self {
+            zone_id: feature.id.clone().unwrap_or_else(|| feature.properties.title.clone()),
+            zone_name: feature.properties.title.clone(),
+            geometry: feature.geometry.clone()
+        }
+    }
+
+    pub fn index_body() -> Value {
+        json!({
+            "mappings": {
+                "properties": {
+                    "zone_id": { "type": "keyword" },
+                    "zone_name": { "type": "keyword" },
+                    "geometry": { "type": "geo_shape" }
+                }
+            }
+        })
+    }
+}
+
+/// Query clause for huts whose `point` lies inside a GeoJSON geometry, such
+/// as a user-drawn polygon.
+pub fn huts_within_shape_query(shape: &Value) -> Value {
+    json!({
+        "geo_shape": {
+            "point": {
+                "shape": shape,
+                "relation": "within"
+            }
+        }
+    })
+}
+
+/// Query clause for huts inside a zone that's already indexed, without
+/// sending its geometry over the wire.
+pub fn huts_within_zone_query(zone_index: &str, zone_id: &str) -> Value {
+    json!({
+        "geo_shape": {
+            "point": {
+                "indexed_shape": {
+                    "index": zone_index,
+                    "id": zone_id,
+                    "path": "geometry"
+                },
+                "relation": "within"
+            }
+        }
+    })
+}
+
+/// Query clause for the zones whose geometry contains a point.
+pub fn zones_containing_point_query(point: &GeoPoint) -> Value {
+    json!({
+        "geo_shape": {
+            "geometry": {
+                "shape": {
+                    "type": "point",
+                    "coordinates": [point.lon, point.lat]
+                },
+                "relation": "intersects"
+            }
+        }
+    })
+}